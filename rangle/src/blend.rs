@@ -0,0 +1,69 @@
+use glam::{Vec3, Vec4, Vec4Swizzles};
+
+use crate::{rangle_display::Color, Rangle};
+
+/// How a fragment's color is combined with the color already in the frame buffer. Alpha
+/// always combines as `src + dst * (1 - src)`.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum BlendMode {
+    /// Overwrites the color.
+    Replace,
+    /// Covers the color by the fragment's alpha.
+    Alpha,
+    /// Adds the fragment's color, scaled by its alpha.
+    Additive,
+    /// Multiplies the color by the fragment's color, tinting by as much as its alpha.
+    Multiply,
+}
+
+impl BlendMode {
+    pub(crate) fn blend(self, dst: Color, src: Color) -> Color {
+        if self == BlendMode::Replace {
+            return src;
+        }
+
+        let (dst, src) = (Rangle::color_to_vec(dst), Rangle::color_to_vec(src));
+        let a = src.w;
+        let rgb = match self {
+            BlendMode::Replace => unreachable!("replacing needs no blending"),
+            BlendMode::Alpha => src.xyz() * a + dst.xyz() * (1.0 - a),
+            BlendMode::Additive => dst.xyz() + src.xyz() * a,
+            BlendMode::Multiply => dst.xyz() * (src.xyz() * a + Vec3::splat(1.0 - a)),
+        };
+
+        Rangle::vec_to_color(Vec4::from((rgb, a + dst.w * (1.0 - a))))
+    }
+
+    /// Whether blending a fragment of this color depends on the color under it. Replacing
+    /// never does, and covering by alpha only does for fragments which are not opaque.
+    pub(crate) fn blends(self, src: Color) -> bool {
+        match self {
+            BlendMode::Replace => false,
+            BlendMode::Alpha => src.3 < 255,
+            BlendMode::Additive | BlendMode::Multiply => true,
+        }
+    }
+}
+
+/// How fragments which are not fully opaque are ordered.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Transparency {
+    /// Blends primitives in the order they are drawn.
+    Ordered,
+    /// Draws the fragments which do not blend first, in the order their primitives are
+    /// drawn, then blends the rest from the farthest primitive to the nearest, by the
+    /// average depth of their corners. Primitives which intersect can still blend in the
+    /// wrong order.
+    Sorted,
+    /// Weighted blended order-independent transparency (McGuire and Bavoil, 2013). Opaque
+    /// fragments are drawn first, then every other fragment in front of them is added to
+    /// a per-pixel average weighted by alpha and nearness, which is composited over the
+    /// opaque color. The blend mode only applies to opaque fragments.
+    WeightedBlended,
+}
+
+/// How much a transparent fragment counts towards the weighted average, given its alpha
+/// and how far it lies from the near plane to the far plane.
+pub(crate) fn weight(alpha: f32, distance: f32) -> f32 {
+    alpha * (3e3 * (1.0 - distance).powi(3)).clamp(1e-2, 3e3)
+}
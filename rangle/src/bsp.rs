@@ -0,0 +1,278 @@
+use glam::DVec3;
+
+use crate::rangle_display::Color;
+
+/// Points come from `f32` screen coordinates, so planes are only trusted to about this
+/// distance.
+const EPSILON: f64 = 1e-3;
+/// How many polygons are tried as the splitting plane of each node.
+const SPLITTER_CANDIDATES: usize = 8;
+
+/// A flat-colored convex polygon in screen space, with depth as the third coordinate.
+#[derive(Clone)]
+pub(crate) struct Polygon {
+    pub(crate) points: Vec<DVec3>,
+    pub(crate) color: Color,
+}
+
+#[derive(Clone, Copy)]
+struct Plane {
+    normal: DVec3,
+    distance: f64,
+}
+
+enum Side {
+    Front,
+    Back,
+    Coplanar,
+    Spanning,
+}
+
+impl Plane {
+    /// Uses Newell's method, which stays stable for nearly collinear points.
+    fn from_polygon(polygon: &Polygon) -> Option<Self> {
+        let mut normal = DVec3::ZERO;
+        for (i, &a) in polygon.points.iter().enumerate() {
+            let b = polygon.points[(i + 1) % polygon.points.len()];
+
+            normal += DVec3::new(
+                (a.y - b.y) * (a.z + b.z),
+                (a.z - b.z) * (a.x + b.x),
+                (a.x - b.x) * (a.y + b.y),
+            );
+        }
+
+        if normal.length() <= EPSILON {
+            return None;
+        }
+        let normal = normal.normalize();
+
+        Some(Plane {
+            normal,
+            distance: normal.dot(polygon.points[0]),
+        })
+    }
+
+    fn signed_distance(&self, point: DVec3) -> f64 {
+        self.normal.dot(point) - self.distance
+    }
+
+    fn classify(&self, polygon: &Polygon) -> Side {
+        let (mut front, mut back) = (false, false);
+        for &point in &polygon.points {
+            let d = self.signed_distance(point);
+            if d > EPSILON {
+                front = true;
+            } else if d < -EPSILON {
+                back = true;
+            }
+        }
+
+        match (front, back) {
+            (true, true) => Side::Spanning,
+            (true, false) => Side::Front,
+            (false, true) => Side::Back,
+            (false, false) => Side::Coplanar,
+        }
+    }
+
+    /// Cuts a spanning polygon in two, returning the front and back halves.
+    fn split(&self, polygon: &Polygon) -> (Polygon, Polygon) {
+        let mut front = vec![];
+        let mut back = vec![];
+
+        let n = polygon.points.len();
+        for i in 0..n {
+            let (a, b) = (polygon.points[i], polygon.points[(i + 1) % n]);
+            let (da, db) = (self.signed_distance(a), self.signed_distance(b));
+
+            if da >= -EPSILON {
+                front.push(a);
+            }
+            if da <= EPSILON {
+                back.push(a);
+            }
+
+            if (da > EPSILON && db < -EPSILON) || (da < -EPSILON && db > EPSILON) {
+                let point = a + (b - a) * (da / (da - db));
+
+                front.push(point);
+                back.push(point);
+            }
+        }
+
+        (
+            Polygon {
+                points: front,
+                color: polygon.color,
+            },
+            Polygon {
+                points: back,
+                color: polygon.color,
+            },
+        )
+    }
+}
+
+struct Face {
+    polygon: Polygon,
+    plane: Plane,
+}
+
+/// Faces still to be built into a subtree, and the node and side it hangs from, if any.
+type WorkItem = (Vec<Face>, Option<(usize, bool)>);
+
+struct Node {
+    polygons: Vec<Polygon>,
+    plane: Plane,
+    front: Option<usize>,
+    back: Option<usize>,
+}
+
+/// A binary space partition of polygons, used to draw them in back to front order
+/// with the painter's algorithm. Polygons which cross a partition are split.
+pub(crate) struct BspTree {
+    nodes: Vec<Node>,
+    root: Option<usize>,
+}
+
+impl BspTree {
+    /// Degenerate polygons are left out, since they cover no area.
+    pub(crate) fn new(polygons: Vec<Polygon>) -> Self {
+        let faces = polygons
+            .into_iter()
+            .filter_map(|polygon| {
+                Plane::from_polygon(&polygon).map(|plane| Face { polygon, plane })
+            })
+            .collect();
+
+        let mut tree = BspTree {
+            nodes: vec![],
+            root: None,
+        };
+
+        // The tree is built without recursion, since meshes can make it very deep.
+        let mut work: Vec<WorkItem> = vec![(faces, None)];
+        while let Some((faces, parent)) = work.pop() {
+            if faces.is_empty() {
+                continue;
+            }
+
+            let splitter = Self::choose_splitter(&faces);
+            let plane = faces[splitter].plane;
+
+            let mut coplanar = vec![];
+            let mut front = vec![];
+            let mut back = vec![];
+            for (i, face) in faces.into_iter().enumerate() {
+                // Clipped polygons may not be exactly planar, but the splitter always stays
+                // at its node so that every step makes progress.
+                if i == splitter {
+                    coplanar.push(face.polygon);
+                    continue;
+                }
+
+                match plane.classify(&face.polygon) {
+                    Side::Coplanar => coplanar.push(face.polygon),
+                    Side::Front => front.push(face),
+                    Side::Back => back.push(face),
+                    Side::Spanning => {
+                        let (front_half, back_half) = plane.split(&face.polygon);
+
+                        if front_half.points.len() >= 3 {
+                            front.push(Face {
+                                polygon: front_half,
+                                plane: face.plane,
+                            });
+                        }
+                        if back_half.points.len() >= 3 {
+                            back.push(Face {
+                                polygon: back_half,
+                                plane: face.plane,
+                            });
+                        }
+                    }
+                }
+            }
+
+            let index = tree.nodes.len();
+            tree.nodes.push(Node {
+                polygons: coplanar,
+                plane,
+                front: None,
+                back: None,
+            });
+
+            match parent {
+                None => tree.root = Some(index),
+                Some((parent, true)) => tree.nodes[parent].front = Some(index),
+                Some((parent, false)) => tree.nodes[parent].back = Some(index),
+            }
+
+            work.push((front, Some((index, true))));
+            work.push((back, Some((index, false))));
+        }
+
+        tree
+    }
+
+    /// Picks the candidate plane which causes the fewest splits, preferring balanced trees.
+    fn choose_splitter(faces: &[Face]) -> usize {
+        let step = (faces.len() / SPLITTER_CANDIDATES).max(1);
+
+        let mut best = (usize::MAX, 0);
+        for candidate in (0..faces.len()).step_by(step).take(SPLITTER_CANDIDATES) {
+            let plane = faces[candidate].plane;
+
+            let (mut splits, mut front, mut back) = (0, 0_usize, 0_usize);
+            for face in faces {
+                match plane.classify(&face.polygon) {
+                    Side::Front => front += 1,
+                    Side::Back => back += 1,
+                    Side::Spanning => splits += 1,
+                    Side::Coplanar => {}
+                }
+            }
+
+            let score = splits * 8 + front.abs_diff(back);
+            if score < best.0 {
+                best = (score, candidate);
+            }
+        }
+
+        best.1
+    }
+
+    /// Returns every polygon ordered from farthest to nearest for a viewer looking along
+    /// `view_direction`.
+    pub(crate) fn into_back_to_front(mut self, view_direction: DVec3) -> Vec<Polygon> {
+        enum Step {
+            Visit(usize),
+            Emit(usize),
+        }
+
+        let mut rv = vec![];
+        let mut steps: Vec<Step> = self.root.into_iter().map(Step::Visit).collect();
+        while let Some(step) = steps.pop() {
+            match step {
+                Step::Visit(index) => {
+                    let node = &self.nodes[index];
+
+                    // The viewer is on the front side when looking against the normal.
+                    let (near, far) = if node.plane.normal.dot(view_direction) < 0.0 {
+                        (node.front, node.back)
+                    } else {
+                        (node.back, node.front)
+                    };
+
+                    steps.extend(near.map(Step::Visit));
+                    steps.push(Step::Emit(index));
+                    steps.extend(far.map(Step::Visit));
+                }
+                Step::Emit(index) => rv.append(&mut self.nodes[index].polygons),
+            }
+        }
+
+        rv
+    }
+}
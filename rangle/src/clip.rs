@@ -0,0 +1,130 @@
+use glam::Vec4;
+
+use crate::{Shader, Varyings};
+
+/// A clip space position with the varyings the vertex shader produced alongside it.
+pub(crate) type ClipVertex = (Vec4, Varyings);
+
+/// The view frustum of `Rangle::compute_projection_matrix` in clip space, as planes whose
+/// dot product with a position is non-negative inside. The depth range is `0 <= z <= w`,
+/// so the near plane also keeps everything in front of the camera. The depth planes come
+/// first, so that every vertex made by the later planes has a positive `w`.
+const CLIP_PLANES: [[f32; 4]; 6] = [
+    [0.0, 0.0, 1.0, 0.0],
+    [0.0, 0.0, -1.0, 1.0],
+    [1.0, 0.0, 0.0, 1.0],
+    [-1.0, 0.0, 0.0, 1.0],
+    [0.0, 1.0, 0.0, 1.0],
+    [0.0, -1.0, 0.0, 1.0],
+];
+
+pub(crate) fn is_inside(position: Vec4) -> bool {
+    CLIP_PLANES
+        .iter()
+        .all(|&plane| Vec4::from(plane).dot(position) >= 0.0)
+}
+
+/// The vertex a clip space fraction `k` of the way from `a` to `b`, with every varying
+/// re-interpolated.
+fn interpolate(a: &ClipVertex, b: &ClipVertex, k: f32, shader: &Shader) -> ClipVertex {
+    let position = a.0 * (1.0 - k) + b.0 * k;
+
+    // Where the new vertex falls between the two in screen space.
+    let t = if position.w > 0.0 {
+        k * b.0.w / position.w
+    } else {
+        k
+    };
+
+    (
+        position,
+        shader.interpolate(&[&a.1, &b.1], &[1.0 - t, t], &[1.0 - k, k]),
+    )
+}
+
+/// Gives every vertex the flat varyings of the first, since clipping can drop it.
+fn apply_flat_varyings(vertices: &mut [ClipVertex], shader: &Shader) {
+    if let Some(((_, provoking), rest)) = vertices.split_first_mut() {
+        for (_, val) in rest {
+            shader.apply_flat_varyings(provoking, val);
+        }
+    }
+}
+
+/// Clips a convex polygon against the view frustum with the Sutherland-Hodgman algorithm.
+/// The result is empty if nothing is visible, and otherwise is a convex polygon with up to
+/// nine vertices for a triangle.
+pub(crate) fn clip_polygon(mut polygon: Vec<ClipVertex>, shader: &Shader) -> Vec<ClipVertex> {
+    apply_flat_varyings(&mut polygon, shader);
+
+    if polygon.iter().all(|(p, _)| is_inside(*p)) {
+        return polygon;
+    }
+
+    for &plane in &CLIP_PLANES {
+        let plane = Vec4::from(plane);
+
+        let n = polygon.len();
+        let mut clipped = Vec::with_capacity(n + 1);
+        for i in 0..n {
+            let (a, b) = (&polygon[i], &polygon[(i + 1) % n]);
+            let (da, db) = (plane.dot(a.0), plane.dot(b.0));
+
+            if da >= 0.0 {
+                clipped.push(a.clone());
+            }
+            if (da >= 0.0) != (db >= 0.0) {
+                clipped.push(interpolate(a, b, da / (da - db), shader));
+            }
+        }
+
+        polygon = clipped;
+        if polygon.len() < 3 {
+            return vec![];
+        }
+    }
+
+    polygon
+}
+
+/// Clips a line segment against the view frustum, returning `None` if nothing is visible.
+pub(crate) fn clip_line(
+    a: ClipVertex,
+    b: ClipVertex,
+    shader: &Shader,
+) -> Option<(ClipVertex, ClipVertex)> {
+    let mut line = [a, b];
+    apply_flat_varyings(&mut line, shader);
+    let [a, b] = line;
+
+    let (mut k1, mut k2) = (0.0_f32, 1.0_f32);
+    for &plane in &CLIP_PLANES {
+        let plane = Vec4::from(plane);
+        let (da, db) = (plane.dot(a.0), plane.dot(b.0));
+
+        if da < 0.0 && db < 0.0 {
+            return None;
+        } else if da < 0.0 {
+            k1 = k1.max(da / (da - db));
+        } else if db < 0.0 {
+            k2 = k2.min(da / (da - db));
+        }
+    }
+
+    if k1 > k2 {
+        return None;
+    }
+
+    let start = if k1 > 0.0 {
+        interpolate(&a, &b, k1, shader)
+    } else {
+        a.clone()
+    };
+    let end = if k2 < 1.0 {
+        interpolate(&a, &b, k2, shader)
+    } else {
+        b
+    };
+
+    Some((start, end))
+}
@@ -0,0 +1,109 @@
+/// How a fragment's depth is compared with the depth already in the buffer. Fragments
+/// which pass are drawn. Stencil tests compare their reference value the same way.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum DepthCompare {
+    Never,
+    Less,
+    LessEqual,
+    Equal,
+    GreaterEqual,
+    Greater,
+    NotEqual,
+    Always,
+}
+
+impl DepthCompare {
+    pub(crate) fn passes<T: PartialOrd>(self, value: T, stored: T) -> bool {
+        match self {
+            DepthCompare::Never => false,
+            DepthCompare::Less => value < stored,
+            DepthCompare::LessEqual => value <= stored,
+            DepthCompare::Equal => value == stored,
+            DepthCompare::GreaterEqual => value >= stored,
+            DepthCompare::Greater => value > stored,
+            DepthCompare::NotEqual => value != stored,
+            DepthCompare::Always => true,
+        }
+    }
+}
+
+/// How fragments are tested against and written to the depth buffer.
+///
+/// The depth buffer holds window depths, which run from `range.0` at the near plane to
+/// `range.1` at the far plane, or the other way around with `reversed_z`.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct DepthState {
+    pub compare: DepthCompare,
+    /// Whether fragments which pass write their depth.
+    pub write: bool,
+    /// The depth the buffer holds where nothing has been drawn.
+    pub clear: f32,
+    /// Swaps the near and far planes, which spreads float precision more evenly over the
+    /// scene when used with `DepthCompare::Greater` and a clear value of 0.
+    pub reversed_z: bool,
+    /// The window depths of the near and far planes.
+    pub range: (f32, f32),
+}
+
+impl Default for DepthState {
+    /// Keeps the nearest fragment, with 0 at the near plane and 1 at the far plane.
+    fn default() -> Self {
+        DepthState {
+            compare: DepthCompare::Less,
+            write: true,
+            clear: 1.0,
+            reversed_z: false,
+            range: (0.0, 1.0),
+        }
+    }
+}
+
+impl DepthState {
+    /// Keeps the nearest fragment, with 1 at the near plane and 0 at the far plane.
+    pub fn reversed() -> Self {
+        DepthState {
+            compare: DepthCompare::Greater,
+            clear: 0.0,
+            reversed_z: true,
+            ..DepthState::default()
+        }
+    }
+
+    /// Maps a normalized device depth, 0 at the near plane and 1 at the far plane, to the
+    /// window depth stored in the buffer.
+    pub(crate) fn window_depth(&self, z: f32) -> f32 {
+        let z = if self.reversed_z { 1.0 - z } else { z };
+
+        self.range.0 + (self.range.1 - self.range.0) * z
+    }
+
+    /// How far a window depth lies from the near plane to the far plane, from 0 to 1.
+    pub(crate) fn distance(&self, depth: f32) -> f32 {
+        let (near, far) = (self.window_depth(0.0), self.window_depth(1.0));
+
+        if near == far {
+            0.0
+        } else {
+            (depth - near) / (far - near)
+        }
+    }
+
+    /// Which way window depth changes towards the camera, 1 if it grows and -1 if it
+    /// shrinks.
+    pub(crate) fn towards_camera(&self) -> f32 {
+        if self.window_depth(0.0) > self.window_depth(1.0) {
+            1.0
+        } else {
+            -1.0
+        }
+    }
+
+    /// The nearer of two window depths.
+    pub(crate) fn nearest(&self, a: f32, b: f32) -> f32 {
+        if (a - b) * self.towards_camera() > 0.0 {
+            a
+        } else {
+            b
+        }
+    }
+}
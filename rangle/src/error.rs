@@ -16,8 +16,54 @@ pub enum RangleError {
 
     #[error("duplicate attribute names")]
     DuplicateShaderAttributes,
+    #[error("duplicate varying names")]
+    DuplicateShaderVaryings,
     #[error("duplicate uniform names")]
     DuplicateShaderUniforms,
+    #[error("no attribute, varying or uniform named `{0}`")]
+    UnknownShaderValue(&'static str),
+    #[error("shader value `{name}` is a {found}, not a {expected}")]
+    MismatchedShaderTypes {
+        name: &'static str,
+        expected: &'static str,
+        found: &'static str,
+    },
+    #[error("a shader used a varying or uniform from another layout")]
+    MismatchedShaderLayout,
+    #[error("the vertex shader returned a position which is not finite")]
+    NonFiniteShaderPosition,
+    #[error("the vertex shader does not set varying `{0}`")]
+    UnsetShaderVarying(&'static str),
+    #[error("attribute `{name}` has {found} values for a model with {expected} vertices")]
+    MismatchedAttributeLength {
+        name: &'static str,
+        expected: usize,
+        found: usize,
+    },
     #[error("missing required shader")]
     MissingShader,
+    #[error("{filename}:{line}: invalid obj token `{token}`")]
+    ObjParse {
+        filename: String,
+        line: usize,
+        token: String,
+    },
+    #[error("{filename}: invalid stl file: {reason}")]
+    StlParse { filename: String, reason: String },
+    #[error("{filename}: invalid ply file: {reason}")]
+    PlyParse { filename: String, reason: String },
+    #[error("no object or group named `{0}`")]
+    UnknownSubMesh(String),
+    #[error("{filename}:{line}: invalid mtl token `{token}`")]
+    MtlParse {
+        filename: String,
+        line: usize,
+        token: String,
+    },
+    #[error("the display does not support vector output")]
+    VectorOutputUnsupported,
+    #[error("vector output cannot hide edges behind surfaces")]
+    VectorHiddenLines,
+    #[error("unsupported anti-aliasing sample count {0}")]
+    InvalidSampleCount(u8),
 }
@@ -9,9 +9,9 @@ pub struct FrameBuffer {
 impl FrameBuffer {
     pub fn new(width: u16, height: u16) -> Result<Self, RangleError> {
         Ok(FrameBuffer {
-            width: width,
-            height: height,
-            buffer: vec![(0, 0, 0, 0); (width as usize * height as usize).into()],
+            width,
+            height,
+            buffer: vec![(0, 0, 0, 0); width as usize * height as usize],
         })
     }
 
@@ -1,50 +1,421 @@
-use std::{cell::RefCell, collections::HashMap, rc::Rc};
-
-use glam::{Mat4, Vec2, Vec4, Vec4Swizzles};
-
+use std::{
+    collections::HashSet,
+    ops::Range,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, RwLock,
+    },
+    thread,
+};
+
+use glam::{DVec3, Mat3, Mat4, Vec2, Vec4, Vec4Swizzles};
+
+use bsp::BspTree;
+use clip::ClipVertex;
+use raster::{Paint, RasterPrimitive, RasterVertex, Tile, TILE_SIZE};
+use rangle_display::Color;
+
+pub use blend::{BlendMode, Transparency};
 pub use camera::Camera;
+pub use depth::{DepthCompare, DepthState};
 pub use frame_buffer::FrameBuffer;
 pub use error::RangleError;
-pub use model::Model;
-pub use rangle_display::RangleDisplay;
-pub use shader::{Shader, ShaderType, ShaderTypeMap};
-
+pub use material::Material;
+pub use model::{Model, SubMesh, SubMeshKind};
+pub use rangle_display::{Primitive, RangleDisplay};
+pub use shader::{
+    Interpolation, Shader, ShaderLayout, ShaderValue, Uniform, Uniforms, Varying, Varyings,
+};
+pub use stencil::{StencilOp, StencilState};
+
+mod blend;
+mod bsp;
 mod camera;
+mod clip;
+mod depth;
 mod frame_buffer;
 pub mod rangle_display;
 pub mod error;
+mod material;
 mod model;
+mod ply;
+mod raster;
+mod resolve;
 mod shader;
+mod stencil;
+
+/// How far the edges drawn over surfaces are pulled towards the camera, on top of their
+/// triangle's depth slope, so that they win depth ties with the surface they outline.
+const EDGE_DEPTH_BIAS: f32 = 1e-5;
 
+/// How many pixels' worth of its triangle's depth slope an edge is pulled towards the
+/// camera by. Lines and their endpoints each round up to half a pixel along both axes
+/// away from the triangle edges they follow.
+const EDGE_SLOPE_BIAS: f32 = 2.0;
+
+#[derive(Clone, Copy)]
 pub enum RangleMode {
     Triangles,
     Lines,
     Points,
+    /// Shaded triangles with their visible edges drawn over them in the wireframe color.
+    SolidWireframe,
+    /// Only the visible edges, with surfaces hiding what is behind them in the background
+    /// color.
+    HiddenLine,
+}
+
+/// Which triangles are skipped based on the way they face the camera.
+#[derive(Clone, Copy, PartialEq)]
+pub enum CullMode {
+    None,
+    Back,
+    Front,
 }
 
+/// The order in which the vertices of a front facing triangle appear on screen.
+#[derive(Clone, Copy, PartialEq)]
+pub enum FrontFace {
+    CounterClockwise,
+    Clockwise,
+}
+
+/// How the ends of lines wider than a pixel are drawn. Thinner lines always stop at their
+/// endpoints.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum LineCap {
+    /// Ends flat at the endpoints.
+    Butt,
+    /// Reaches past the endpoints by half the line width.
+    Square,
+    /// Ends in a half circle around each endpoint.
+    Round,
+}
+
+/// The shape points are drawn as.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum PointShape {
+    Square,
+    Round,
+}
+
+/// Whether scenes are rasterized into the frame buffer or handed to the display as
+/// depth-sorted primitives.
+pub enum RangleOutput {
+    Raster,
+    Vector,
+}
+
+/// How edges are smoothed when rasterizing.
+#[derive(Clone, Copy, PartialEq)]
+pub enum AntiAliasing {
+    None,
+    /// Renders at `factor` times the resolution along each axis, then filters it down.
+    Ssaa {
+        factor: u8,
+        filter: ResolveFilter,
+    },
+    /// Tests coverage and depth at 2, 4 or 8 points in each pixel, but runs the fragment
+    /// shader only once per pixel.
+    Msaa {
+        samples: u8,
+    },
+}
+
+/// The filter used to shrink a supersampled image to the frame buffer.
+#[derive(Clone, Copy, PartialEq)]
+pub enum ResolveFilter {
+    /// Averages the pixels each frame buffer pixel covers.
+    Box,
+    /// A two-lobed Lanczos filter, which is sharper but can ring near hard edges.
+    Lanczos,
+}
+
+impl AntiAliasing {
+    /// How many render target pixels span each frame buffer pixel along each axis.
+    fn scale(&self) -> usize {
+        match *self {
+            AntiAliasing::Ssaa { factor, .. } => factor as usize,
+            _ => 1,
+        }
+    }
+
+    fn samples(&self) -> usize {
+        match *self {
+            AntiAliasing::Msaa { samples } => samples as usize,
+            _ => 1,
+        }
+    }
+}
+
+/// A model, its shader and the stencil state it is drawn with, shared with the caller and
+/// with render threads.
+type SharedModel = (Arc<RwLock<Model>>, Arc<RwLock<Shader>>, StencilState);
+
 pub struct Rangle {
     display: Box<dyn RangleDisplay>,
     display_mode: RangleMode,
-    models: Vec<(Rc<RefCell<Model>>, Rc<RefCell<Shader>>)>,
+    output: RangleOutput,
+    cull_mode: CullMode,
+    front_face: FrontFace,
+    thread_count: usize,
+    anti_aliasing: AntiAliasing,
+    line_width: f32,
+    line_cap: LineCap,
+    smooth_lines: bool,
+    point_size: f32,
+    point_shape: PointShape,
+    wireframe_color: Color,
+    depth_state: DepthState,
+    blend_mode: BlendMode,
+    transparency: Transparency,
+    models: Vec<SharedModel>,
     frame_buffer: FrameBuffer,
     depth_buffer: Vec<f32>,
+    stencil_buffer: Vec<u8>,
+}
+
+/// The settings primitive assembly reads, copied out of `Rangle` so that worker threads
+/// can share them.
+#[derive(Clone, Copy)]
+struct PipelineState {
+    display_mode: RangleMode,
+    cull_mode: CullMode,
+    front_face: FrontFace,
+    size: (u16, u16),
+    /// The supersampling factor, which lines and points are widened by so that they keep
+    /// their width once filtered down.
+    scale: usize,
+    samples: usize,
+    /// Line width in render target pixels.
+    line_width: f32,
+    line_cap: LineCap,
+    smooth_lines: bool,
+    /// Point size in frame buffer pixels, unless the vertex shader sets its own.
+    point_size: f32,
+    point_shape: PointShape,
+    depth: DepthState,
+    blend_mode: BlendMode,
+    transparency: Transparency,
+}
+
+impl PipelineState {
+    /// Whether a triangle should be skipped, given the clip space positions of its corners.
+    /// The winding is found from the determinant of the homogeneous screen positions, which
+    /// stays correct for corners behind the camera.
+    fn is_culled(&self, p1: Vec4, p2: Vec4, p3: Vec4) -> bool {
+        if self.cull_mode == CullMode::None {
+            return false;
+        }
+
+        let determinant = Mat3::from_cols(p1.xyw(), p2.xyw(), p3.xyw()).determinant();
+        let front_facing = match self.front_face {
+            FrontFace::CounterClockwise => determinant > 0.0,
+            FrontFace::Clockwise => determinant < 0.0,
+        };
+
+        match self.cull_mode {
+            CullMode::None => false,
+            CullMode::Back => !front_facing,
+            CullMode::Front => front_facing,
+        }
+    }
+
+    fn raster_vertex(&self, (position, val): ClipVertex) -> RasterVertex {
+        let p = position / position.w;
+
+        // Pixel centers stay on whole numbers in the render target, with each frame buffer
+        // pixel center between those of the pixels it covers.
+        let position = Rangle::transform_coords_normalized_to_screen(self.size, p.x, p.y);
+        let scale = self.scale as f32;
+
+        RasterVertex {
+            position: (position + Vec2::splat(0.5)) * scale - Vec2::splat(0.5),
+            depth: self.depth.window_depth(p.z),
+            val,
+        }
+    }
+
+    /// The size in frame buffer pixels of the point at a vertex.
+    fn point_size(&self, val: &Varyings) -> f32 {
+        val.point_size.map_or(self.point_size, |size| size.max(1.0))
+    }
+
+    /// How far the edges of a triangle are pulled towards the camera, given at least three
+    /// of the corners left after clipping.
+    fn edge_depth_bias(&self, polygon: &[RasterVertex]) -> f32 {
+        let (a, b, c) = (&polygon[0], &polygon[1], &polygon[2]);
+        let (ab, ac) = (b.position - a.position, c.position - a.position);
+        let area = ab.x * ac.y - ab.y * ac.x;
+        if area == 0.0 {
+            return EDGE_DEPTH_BIAS;
+        }
+
+        // The depth plane's change per pixel along each axis.
+        let (dab, dac) = (b.depth - a.depth, c.depth - a.depth);
+        let dz_dx = (dab * ac.y - dac * ab.y) / area;
+        let dz_dy = (dac * ab.x - dab * ac.x) / area;
+
+        EDGE_DEPTH_BIAS + dz_dx.abs().max(dz_dy.abs()) * EDGE_SLOPE_BIAS
+    }
+
+    /// Turns the vertices of the triangles which are not culled into points, drawing each
+    /// vertex once however many triangles share it.
+    fn assemble_points(
+        &self,
+        vertex_indices: &[usize],
+        vertices: &[ClipVertex],
+    ) -> Vec<RasterPrimitive> {
+        let mut rv = vec![];
+        let mut seen_vertices = HashSet::new();
+        for (triangle, indices) in vertices.chunks_exact(3).zip(vertex_indices.chunks_exact(3)) {
+            if self.is_culled(triangle[0].0, triangle[1].0, triangle[2].0) {
+                continue;
+            }
+
+            for (&index, vertex) in indices.iter().zip(triangle) {
+                if seen_vertices.insert(index) && clip::is_inside(vertex.0) {
+                    let size = self.point_size(&vertex.1) * self.scale as f32;
+
+                    rv.push(RasterPrimitive::Point(
+                        self.raster_vertex(vertex.clone()),
+                        size,
+                    ));
+                }
+            }
+        }
+
+        rv
+    }
+
+    /// Culls and clips one triangle of vertex shader output, turning what is left into
+    /// filled surfaces and edges for the display mode. Edges drawn over surfaces are pulled
+    /// towards the camera by `edge_depth_bias`.
+    fn assemble(
+        &self,
+        triangle: &[ClipVertex],
+        shader: &Shader,
+        surfaces: &mut Vec<RasterPrimitive>,
+        edges: &mut Vec<RasterPrimitive>,
+    ) {
+        if self.is_culled(triangle[0].0, triangle[1].0, triangle[2].0) {
+            return;
+        }
+
+        let (fill, outline) = match self.display_mode {
+            RangleMode::Triangles => (true, false),
+            RangleMode::Lines => (false, true),
+            RangleMode::SolidWireframe | RangleMode::HiddenLine => (true, true),
+            RangleMode::Points => unreachable!("points are assembled by `assemble_points`"),
+        };
+
+        let mut bias = 0.0;
+        if fill {
+            let polygon: Vec<RasterVertex> = clip::clip_polygon(triangle.to_vec(), shader)
+                .into_iter()
+                .map(|vertex| self.raster_vertex(vertex))
+                .collect();
+
+            // Clipping leaves a convex polygon, which is drawn as a fan.
+            for j in 1..polygon.len().saturating_sub(1) {
+                surfaces.push(RasterPrimitive::Triangle([
+                    polygon[0].clone(),
+                    polygon[j].clone(),
+                    polygon[j + 1].clone(),
+                ]));
+            }
+
+            if outline && polygon.len() >= 3 {
+                bias = self.edge_depth_bias(&polygon);
+            }
+        }
+
+        if outline {
+            for &(a, b) in &[(0, 1), (1, 2), (2, 0)] {
+                let line = clip::clip_line(triangle[a].clone(), triangle[b].clone(), shader);
+
+                if let Some((v1, v2)) = line {
+                    let (mut v1, mut v2) = (self.raster_vertex(v1), self.raster_vertex(v2));
+                    v1.depth += bias * self.depth.towards_camera();
+                    v2.depth += bias * self.depth.towards_camera();
+
+                    edges.push(RasterPrimitive::Line([v1, v2]));
+                }
+            }
+        }
+    }
+}
+
+/// Runs `f` on `threads` threads, passing each its index, and concatenates the results in
+/// thread order.
+fn run_parallel<R: Send>(threads: usize, f: impl Fn(usize) -> Vec<R> + Sync) -> Vec<R> {
+    if threads <= 1 {
+        return f(0);
+    }
+
+    thread::scope(|scope| {
+        let f = &f;
+        let handles: Vec<_> = (0..threads).map(|i| scope.spawn(move || f(i))).collect();
+
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().unwrap())
+            .collect()
+    })
+}
+
+/// The contiguous share of `0..count` which thread `index` of `threads` works on.
+fn thread_range(index: usize, threads: usize, count: usize) -> Range<usize> {
+    let chunk = (count + threads - 1) / threads.max(1);
+
+    (index * chunk).min(count)..((index + 1) * chunk).min(count)
 }
 
 impl Rangle {
     pub fn new(display: Box<dyn RangleDisplay>) -> Result<Self, RangleError> {
         let display_mode = RangleMode::Triangles;
+        let output = RangleOutput::Raster;
+        let cull_mode = CullMode::None;
+        let front_face = FrontFace::CounterClockwise;
+        let thread_count = 1;
+        let anti_aliasing = AntiAliasing::None;
+        let line_width = 1.0;
+        let line_cap = LineCap::Butt;
+        let smooth_lines = false;
+        let point_size = 1.0;
+        let point_shape = PointShape::Square;
+        let wireframe_color = (0, 0, 0, 255);
+        let depth_state = DepthState::default();
+        let blend_mode = BlendMode::Replace;
+        let transparency = Transparency::Ordered;
         let models = vec![];
         let (w, h) = display.get_size();
         let size = w as usize * h as usize;
         let frame_buffer = FrameBuffer::new(w, h)?;
-        let depth_buffer = vec![-2.0; size];
+        let depth_buffer = vec![depth_state.clear; size];
+        let stencil_buffer = vec![0; size];
 
         Ok(Rangle {
             display,
             display_mode,
+            output,
+            cull_mode,
+            front_face,
+            thread_count,
+            anti_aliasing,
+            line_width,
+            line_cap,
+            smooth_lines,
+            point_size,
+            point_shape,
+            wireframe_color,
+            depth_state,
+            blend_mode,
+            transparency,
             models,
             frame_buffer,
             depth_buffer,
+            stencil_buffer,
         })
     }
 
@@ -56,6 +427,124 @@ impl Rangle {
         self.display_mode = mode;
     }
 
+    pub fn set_output(&mut self, output: RangleOutput) {
+        self.output = output;
+    }
+
+    /// Culling applies in every display mode, so in lines mode it hides the edges of
+    /// culled triangles.
+    pub fn set_cull_mode(&mut self, cull_mode: CullMode) {
+        self.cull_mode = cull_mode;
+    }
+
+    pub fn set_front_face(&mut self, front_face: FrontFace) {
+        self.front_face = front_face;
+    }
+
+    /// Sets how many threads run the vertex shaders and rasterize screen tiles.
+    pub fn set_thread_count(&mut self, threads: usize) {
+        self.thread_count = threads.max(1);
+    }
+
+    /// Supersampling factors run from 1 to 8, and multisampling takes 1, 2, 4 or 8 samples.
+    pub fn set_anti_aliasing(&mut self, anti_aliasing: AntiAliasing) -> Result<(), RangleError> {
+        match anti_aliasing {
+            AntiAliasing::Ssaa { factor, .. } if factor == 0 || factor > 8 => {
+                Err(RangleError::InvalidSampleCount(factor))
+            }
+            AntiAliasing::Msaa { samples } if !samples.is_power_of_two() || samples > 8 => {
+                Err(RangleError::InvalidSampleCount(samples))
+            }
+            _ => {
+                self.anti_aliasing = anti_aliasing;
+
+                Ok(())
+            }
+        }
+    }
+
+    /// Sets the width of lines in frame buffer pixels. Lines are never drawn thinner than
+    /// one pixel.
+    pub fn set_line_width(&mut self, width: f32) {
+        self.line_width = width.max(1.0);
+    }
+
+    pub fn set_line_cap(&mut self, cap: LineCap) {
+        self.line_cap = cap;
+    }
+
+    /// Smooths lines by blending them into what is behind them by how much of each pixel
+    /// they cover. Lines a pixel wide use Xiaolin Wu's algorithm.
+    pub fn set_smooth_lines(&mut self, smooth: bool) {
+        self.smooth_lines = smooth;
+    }
+
+    /// Sets the size of points in frame buffer pixels. A vertex shader can override it for
+    /// each vertex with `Varyings::set_point_size`. Points are never drawn smaller than one
+    /// pixel.
+    pub fn set_point_size(&mut self, size: f32) {
+        self.point_size = size.max(1.0);
+    }
+
+    pub fn set_point_shape(&mut self, shape: PointShape) {
+        self.point_shape = shape;
+    }
+
+    /// Sets the color of the edges drawn over surfaces in `RangleMode::SolidWireframe`.
+    pub fn set_wireframe_color(&mut self, color: Vec4) {
+        self.wireframe_color = Self::vec_to_color(color);
+    }
+
+    /// Sets how fragments are depth tested, clearing the depth buffer to the new clear
+    /// value.
+    pub fn set_depth_state(&mut self, depth_state: DepthState) {
+        self.depth_state = depth_state;
+        self.depth_buffer.fill(depth_state.clear);
+    }
+
+    /// Sets how fragments blend into the frame buffer. Vector output draws every fragment
+    /// as it is.
+    pub fn set_blend_mode(&mut self, blend_mode: BlendMode) {
+        self.blend_mode = blend_mode;
+    }
+
+    /// Sets how fragments which are not fully opaque are ordered before they blend.
+    pub fn set_transparency(&mut self, transparency: Transparency) {
+        self.transparency = transparency;
+    }
+
+    /// The window depth of each pixel of the last rasterized scene, row by row from the top
+    /// left. Pixels nothing was drawn over hold the depth state's clear value.
+    pub fn get_depth_buffer(&self) -> &[f32] {
+        &self.depth_buffer
+    }
+
+    /// The stencil value of each pixel of the last rasterized scene, laid out like the
+    /// depth buffer. Multisampled and supersampled pixels keep the value of their first
+    /// sample.
+    pub fn get_stencil_buffer(&self) -> &[u8] {
+        &self.stencil_buffer
+    }
+
+    fn pipeline_state(&self) -> PipelineState {
+        PipelineState {
+            display_mode: self.display_mode,
+            cull_mode: self.cull_mode,
+            front_face: self.front_face,
+            size: self.get_size(),
+            scale: self.anti_aliasing.scale(),
+            samples: self.anti_aliasing.samples(),
+            line_width: self.line_width * self.anti_aliasing.scale() as f32,
+            line_cap: self.line_cap,
+            smooth_lines: self.smooth_lines,
+            point_size: self.point_size,
+            point_shape: self.point_shape,
+            depth: self.depth_state,
+            blend_mode: self.blend_mode,
+            transparency: self.transparency,
+        }
+    }
+
     pub fn compute_projection_matrix(
         fov: f32,
         aspect_ratio: f32,
@@ -65,414 +554,1620 @@ impl Rangle {
         Mat4::perspective_rh(fov, aspect_ratio, z_near, z_far)
     }
 
-    fn transform_coords_framebuffer_to_barycentric(
-        px: u16,
-        py: u16,
-        x1: u16,
-        y1: u16,
-        x2: u16,
-        y2: u16,
-        x3: u16,
-        y3: u16,
-    ) -> (f32, f32, f32) {
-        let (px, py) = (px as f32, py as f32);
-        let (x1, y1) = (x1 as f32, y1 as f32);
-        let (x2, y2) = (x2 as f32, y2 as f32);
-        let (x3, y3) = (x3 as f32, y3 as f32);
-
-        if px == x1 && py == y1 && px == x2 && py == y2 && px == x3 && py == y3 {
-            return (1.0 / 3.0, 1.0 / 3.0, 1.0 / 3.0);
-        } else if px == x1 && py == y1 && px == x2 && py == y2 {
-            return (0.5, 0.5, 0.0);
-        } else if px == x1 && py == y1 && px == x3 && py == y3 {
-            return (0.5, 0.0, 0.5);
-        } else if px == x2 && py == y2 && px == x3 && py == y3 {
-            return (0.0, 0.5, 0.5);
-        } else {
-            let mut a = (y2 - y3) * (px - x3) + (x3 - x2) * (py - y3);
-            if a != 0.0 {
-                a /= (y2 - y3) * (x1 - x3) + (x3 - x2) * (y1 - y3);
-            }
+    /// Interpolates between two vertices a screen space fraction `k` of the way along, into
+    /// `rv`.
+    fn interpolate_line(
+        val1: &Varyings,
+        val2: &Varyings,
+        k: f32,
+        shader: &Shader,
+        rv: &mut Varyings,
+    ) {
+        let (w1, w2) = (val1.position.w, val2.position.w);
 
-            let mut b = (y3 - y1) * (px - x3) + (x1 - x3) * (py - y3);
-            if b != 0.0 {
-                b /= (y2 - y3) * (x1 - x3) + (x3 - x2) * (y1 - y3);
-            }
+        let (p1, p2) = ((1.0 - k) / w1, k / w2);
+        let sum = p1 + p2;
+
+        shader.interpolate_into(&[val1, val2], &[1.0 - k, k], &[p1 / sum, p2 / sum], rv);
+    }
 
-            let c = 1.0 - a - b;
+    /// Interpolates between three vertices at the screen space barycentric coordinates
+    /// `a`, `b` and `c`, into `rv`.
+    fn interpolate_triangle(
+        (a, b, c): (f32, f32, f32),
+        val1: &Varyings,
+        val2: &Varyings,
+        val3: &Varyings,
+        shader: &Shader,
+        rv: &mut Varyings,
+    ) {
+        let (w1, w2, w3) = (val1.position.w, val2.position.w, val3.position.w);
 
-            return (a, b, c);
-        }
+        let (p1, p2, p3) = (a / w1, b / w2, c / w3);
+        let sum = p1 + p2 + p3;
+
+        shader.interpolate_into(
+            &[val1, val2, val3],
+            &[a, b, c],
+            &[p1 / sum, p2 / sum, p3 / sum],
+            rv,
+        );
     }
 
-    fn interpolate_line(
-        val1: &ShaderTypeMap,
-        val2: &ShaderTypeMap,
-        k: f32,
-    ) -> HashMap<&'static str, ShaderType> {
-        if !(val1.len() == val2.len() && val1.keys().all(|&key| val2.contains_key(key))) {
-            panic!("vals must have the same keyset");
-        }
+    /// Maps normalized device coordinates to the frame buffer, with pixel centers on whole
+    /// numbers.
+    fn transform_coords_normalized_to_screen((width, height): (u16, u16), x: f32, y: f32) -> Vec2 {
+        Vec2::new(
+            ((-x + 1.0) / 2.0) * (width - 1) as f32,
+            ((-y + 1.0) / 2.0) * (height - 1) as f32,
+        )
+    }
+
+    fn color_to_vec(color: Color) -> Vec4 {
+        Vec4::new(
+            color.0 as f32,
+            color.1 as f32,
+            color.2 as f32,
+            color.3 as f32,
+        ) / 255.0
+    }
 
-        let mut rv = HashMap::new();
-        let mut v2;
-        for (&key, &v1) in val1 {
-            v2 = val2[key];
-            match (v1, v2) {
-                (ShaderType::Vec3(v1), ShaderType::Vec3(v2)) => {
-                    let new_v = v1 * (1.0 - k) + v2 * k;
+    fn vec_to_color(val: Vec4) -> Color {
+        let color_vec = (val
+            .max(Vec4::new(0.0, 0.0, 0.0, 0.0))
+            .min(Vec4::new(1.0, 1.0, 1.0, 1.0))
+            * 255.0)
+            .round();
+
+        (
+            color_vec.x as u8,
+            color_vec.y as u8,
+            color_vec.z as u8,
+            color_vec.w as u8,
+        )
+    }
 
-                    rv.insert(key, ShaderType::Vec3(new_v));
+    /// Runs the vertex shader for every corner of a model, split across threads.
+    fn run_vertex_stage(threads: usize, model: &Model, shader: &Shader) -> Vec<ClipVertex> {
+        let count = model.vertex_indices.len();
+
+        run_parallel(threads, |thread| {
+            thread_range(thread, threads, count)
+                .map(|i| {
+                    let val = shader.run_vertex(i);
+
+                    (val.position, val)
+                })
+                .collect()
+        })
+    }
+
+    /// Rasterizes every model into the frame and depth buffers. Primitives are binned into
+    /// screen tiles, which threads then render independently. Multisampled tiles are
+    /// resolved as they finish, and supersampled images are filtered down at the end.
+    /// Edges drawn over surfaces come after every surface, so that only the finished depth
+    /// buffer decides which of them are hidden.
+    fn render_tiles(&mut self) {
+        let threads = self.thread_count;
+        let state = self.pipeline_state();
+        let (width, height) = self.get_size();
+        let (width, height) = (width as usize * state.scale, height as usize * state.scale);
+
+        let models: Vec<_> = self
+            .models
+            .iter()
+            .map(|(model, shader, stencil)| {
+                (model.read().unwrap(), shader.read().unwrap(), *stencil)
+            })
+            .collect();
+
+        let background = self.display.get_background_color();
+        let surface_paint = |i: usize| match state.display_mode {
+            RangleMode::HiddenLine => Paint::Flat(background),
+            _ => Paint::Shaded(&models[i].1),
+        };
+        let edge_paint = |i: usize| match state.display_mode {
+            RangleMode::SolidWireframe => Paint::Flat(self.wireframe_color),
+            _ => Paint::Shaded(&models[i].1),
+        };
+
+        let mut primitives = vec![];
+        let mut edges = vec![];
+        for (i, (model, shader, stencil)) in models.iter().enumerate() {
+            let vertices = Self::run_vertex_stage(threads, model, shader);
+
+            if let RangleMode::Points = state.display_mode {
+                let points = state.assemble_points(&model.vertex_indices, &vertices);
+                primitives.extend(
+                    points
+                        .into_iter()
+                        .map(|primitive| (surface_paint(i), *stencil, primitive)),
+                );
+
+                continue;
+            }
+
+            let triangle_count = vertices.len() / 3;
+            let assembled = run_parallel(threads, |thread| {
+                let (mut surfaces, mut edges) = (vec![], vec![]);
+                for j in thread_range(thread, threads, triangle_count) {
+                    state.assemble(
+                        &vertices[j * 3..j * 3 + 3],
+                        shader,
+                        &mut surfaces,
+                        &mut edges,
+                    );
                 }
-                (ShaderType::Vec4(v1), ShaderType::Vec4(v2)) => {
-                    let new_v = v1 * (1.0 - k) + v2 * k;
 
-                    rv.insert(key, ShaderType::Vec4(new_v));
+                vec![(surfaces, edges)]
+            });
+            for (thread_surfaces, thread_edges) in assembled {
+                primitives.extend(
+                    thread_surfaces
+                        .into_iter()
+                        .map(|p| (surface_paint(i), *stencil, p)),
+                );
+                edges.extend(
+                    thread_edges
+                        .into_iter()
+                        .map(|p| (edge_paint(i), *stencil, p)),
+                );
+            }
+        }
+        let surface_count = primitives.len();
+        primitives.append(&mut edges);
+
+        // Each tile lists the primitives which may touch it, in the order they were drawn.
+        // Wide lines and off-center samples reach past the vertices, by up to the line width
+        // for the corners of square caps.
+        let tiles_x = width.div_ceil(TILE_SIZE);
+        let tiles_y = height.div_ceil(TILE_SIZE);
+        let bin = |order: &[usize]| {
+            let mut bins = vec![vec![]; tiles_x * tiles_y];
+            for &i in order {
+                let (min, max) = primitives[i].2.bounds();
+                let reach = Vec2::splat(state.line_width + 1.0);
+                let (min, max) = (min - reach, max + reach);
+                if max.x < 0.0 || max.y < 0.0 || min.x >= width as f32 || min.y >= height as f32 {
+                    continue;
                 }
-                _ => {
-                    unimplemented!()
+
+                let tx_min = min.x.max(0.0) as usize / TILE_SIZE;
+                let ty_min = min.y.max(0.0) as usize / TILE_SIZE;
+                let tx_max = (max.x as usize).min(width - 1) / TILE_SIZE;
+                let ty_max = (max.y as usize).min(height - 1) / TILE_SIZE;
+                for ty in ty_min..=ty_max {
+                    for tx in tx_min..=tx_max {
+                        bins[ty * tiles_x + tx].push(i);
+                    }
                 }
             }
+
+            bins
+        };
+        let order: Vec<_> = (0..primitives.len()).collect();
+        let bins = bin(&order);
+
+        // Sorted transparency blends the surfaces from the farthest to the nearest, then
+        // the edges in the order they were drawn.
+        let sorted_bins = match state.transparency {
+            Transparency::Sorted => {
+                let nearness = |i: &usize| primitives[*i].2.depth() * state.depth.towards_camera();
+                let mut order = order;
+                order[..surface_count].sort_by(|a, b| nearness(a).total_cmp(&nearness(b)));
+
+                bin(&order)
+            }
+            _ => vec![],
+        };
+
+        // Threads take the next unrendered tile until none are left.
+        let next_tile = AtomicUsize::new(0);
+        let tiles = run_parallel(threads, |_| {
+            let mut rv = vec![];
+            loop {
+                let index = next_tile.fetch_add(1, Ordering::Relaxed);
+                if index >= bins.len() {
+                    break;
+                }
+
+                let (x, y) = ((index % tiles_x) * TILE_SIZE, (index / tiles_x) * TILE_SIZE);
+                let mut tile = Tile::new(
+                    x,
+                    y,
+                    TILE_SIZE.min(width - x),
+                    TILE_SIZE.min(height - y),
+                    background,
+                    &state,
+                );
+                // Sorted and weighted blended transparency draw every fragment which does
+                // not blend before the rest.
+                let transparent_passes: &[bool] = match state.transparency {
+                    Transparency::Ordered => &[false],
+                    _ => &[false, true],
+                };
+                for &transparent_pass in transparent_passes {
+                    tile.transparent_pass = transparent_pass;
+
+                    let bin = match state.transparency {
+                        Transparency::Sorted if transparent_pass => &sorted_bins[index],
+                        _ => &bins[index],
+                    };
+                    for &i in bin {
+                        let (paint, stencil, primitive) = &primitives[i];
+
+                        tile.stencil = *stencil;
+                        tile.draw(primitive, paint);
+                    }
+                }
+
+                rv.push(tile.resolve());
+            }
+
+            rv
+        });
+
+        let mut colors = vec![background; width * height];
+        let mut depths = vec![state.depth.clear; width * height];
+        let mut stencils = vec![0; width * height];
+        for tile in tiles {
+            for row in 0..tile.height {
+                let start = (tile.y + row) * width + tile.x;
+                let source = row * tile.width..(row + 1) * tile.width;
+
+                colors[start..start + tile.width].copy_from_slice(&tile.colors[source.clone()]);
+                depths[start..start + tile.width].copy_from_slice(&tile.depths[source.clone()]);
+                stencils[start..start + tile.width].copy_from_slice(&tile.stencils[source]);
+            }
         }
 
-        rv
-    }
+        if let AntiAliasing::Ssaa { filter, .. } = self.anti_aliasing {
+            let size = (width / state.scale, height / state.scale);
 
-    fn interpolate_triangle(
-        a: f32,
-        b: f32,
-        c: f32,
-        val1: &ShaderTypeMap,
-        val2: &ShaderTypeMap,
-        val3: &ShaderTypeMap,
-    ) -> ShaderTypeMap {
-        if !(val1.len() == val2.len()
-            && val1.len() == val3.len()
-            && val1
-                .keys()
-                .all(|&key| val2.contains_key(key) && val3.contains_key(key)))
-        {
-            panic!("vals must have the same keyset");
+            self.frame_buffer.buffer =
+                resolve::downsample_colors(&colors, size, state.scale, filter);
+            self.depth_buffer =
+                resolve::downsample_depths(&depths, size, state.scale, &state.depth);
+            self.stencil_buffer = resolve::downsample_stencils(&stencils, size, state.scale);
+        } else {
+            self.frame_buffer.buffer = colors;
+            self.depth_buffer = depths;
+            self.stencil_buffer = stencils;
         }
+    }
 
-        let mut rv = HashMap::new();
-        let mut v2;
-        let mut v3;
-        for (&key, &v1) in val1 {
-            v2 = val2[key];
-            v3 = val3[key];
-            match (v1, v2, v3) {
-                (ShaderType::Vec3(v1), ShaderType::Vec3(v2), ShaderType::Vec3(v3)) => {
-                    let new_v = v1 * a + v2 * b + v3 * c;
-
-                    rv.insert(key, ShaderType::Vec3(new_v));
-                }
-                (ShaderType::Vec4(v1), ShaderType::Vec4(v2), ShaderType::Vec4(v3)) => {
-                    let new_v = v1 * a + v2 * b + v3 * c;
+    /// Shades every primitive of every model with a single fragment and orders them from
+    /// farthest to nearest. Triangles are sorted with a BSP tree, splitting them where
+    /// they intersect or overlap cyclically; lines and points are sorted by depth.
+    /// Primitives whose fragment is discarded are left out.
+    fn collect_primitives(&self) -> Vec<Primitive> {
+        let state = self.pipeline_state();
+        let (width, height) = self.get_size();
+
+        // Depth is stretched to about the size of the screen so one epsilon suits every axis.
+        let depth_scale = width.max(height) as f64;
+        let to_screen = |position: Vec4| {
+            let p = position / position.w;
+
+            (
+                Self::transform_coords_normalized_to_screen(state.size, p.x, p.y),
+                p.z,
+            )
+        };
+
+        let mut polygons = vec![];
+        let mut sorted = vec![];
+        for (model, shader, _) in &self.models {
+            let model = model.read().unwrap();
+            let shader = shader.read().unwrap();
+
+            let vertices = Self::run_vertex_stage(self.thread_count, &model, &shader);
 
-                    rv.insert(key, ShaderType::Vec4(new_v));
+            // Edges and vertices shared between triangles are only drawn once.
+            let mut seen_edges = HashSet::new();
+            let mut seen_vertices = HashSet::new();
+            for (i, triangle) in vertices.chunks_exact(3).enumerate() {
+                let indices = &model.vertex_indices[i * 3..i * 3 + 3];
+
+                if state.is_culled(triangle[0].0, triangle[1].0, triangle[2].0) {
+                    continue;
                 }
-                _ => {
-                    unimplemented!()
+
+                match self.display_mode {
+                    RangleMode::Triangles => {
+                        let polygon = clip::clip_polygon(triangle.to_vec(), &shader);
+                        if polygon.is_empty() {
+                            continue;
+                        }
+
+                        let third = 1.0 / 3.0;
+                        let mut val = Varyings::default();
+                        Self::interpolate_triangle(
+                            (third, third, third),
+                            &triangle[0].1,
+                            &triangle[1].1,
+                            &triangle[2].1,
+                            &shader,
+                            &mut val,
+                        );
+                        let color = match shader.run_fragment(&val) {
+                            Some(v) => Self::vec_to_color(v),
+                            None => continue,
+                        };
+
+                        polygons.push(bsp::Polygon {
+                            points: polygon
+                                .iter()
+                                .map(|(p, _)| {
+                                    let (s, z) = to_screen(*p);
+                                    DVec3::new(s.x as f64, s.y as f64, z as f64 * depth_scale)
+                                })
+                                .collect(),
+                            color,
+                        });
+                    }
+                    RangleMode::Lines => {
+                        for &(a, b) in &[(0, 1), (1, 2), (2, 0)] {
+                            let (ia, ib) = (indices[a], indices[b]);
+                            if !seen_edges.insert((ia.min(ib), ia.max(ib))) {
+                                continue;
+                            }
+
+                            let line =
+                                clip::clip_line(triangle[a].clone(), triangle[b].clone(), &shader);
+                            let ((pa, va), (pb, vb)) = match line {
+                                Some(v) => v,
+                                None => continue,
+                            };
+
+                            let mut val = Varyings::default();
+                            Self::interpolate_line(&va, &vb, 0.5, &shader, &mut val);
+                            let color = match shader.run_fragment(&val) {
+                                Some(v) => Self::vec_to_color(v),
+                                None => continue,
+                            };
+                            let ((start, za), (end, zb)) = (to_screen(pa), to_screen(pb));
+
+                            sorted.push((
+                                (za + zb) / 2.0,
+                                Primitive::Line {
+                                    start,
+                                    end,
+                                    width: self.line_width,
+                                    cap: self.line_cap,
+                                    color,
+                                },
+                            ));
+                        }
+                    }
+                    RangleMode::SolidWireframe | RangleMode::HiddenLine => {
+                        unreachable!("vector output cannot hide edges behind surfaces")
+                    }
+                    RangleMode::Points => {
+                        for (&index, (p, val)) in indices.iter().zip(triangle) {
+                            if !seen_vertices.insert(index) || !clip::is_inside(*p) {
+                                continue;
+                            }
+
+                            let (position, z) = to_screen(*p);
+                            let color = match shader.run_fragment(val) {
+                                Some(v) => Self::vec_to_color(v),
+                                None => continue,
+                            };
+
+                            sorted.push((
+                                z,
+                                Primitive::Point {
+                                    position,
+                                    size: state.point_size(val),
+                                    shape: self.point_shape,
+                                    color,
+                                },
+                            ));
+                        }
+                    }
                 }
             }
         }
 
+        // Larger depths are farther from the camera.
+        sorted.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+        let mut rv: Vec<Primitive> = sorted.into_iter().map(|(_, p)| p).collect();
+        rv.extend(
+            BspTree::new(polygons)
+                .into_back_to_front(DVec3::Z)
+                .into_iter()
+                .map(|polygon| Primitive::Polygon {
+                    points: polygon
+                        .points
+                        .iter()
+                        .map(|p| Vec2::new(p.x as f32, p.y as f32))
+                        .collect(),
+                    color: polygon.color,
+                }),
+        );
+
         rv
     }
 
-    fn transform_coords_normalized_to_framebuffer(&self, x: f32, y: f32) -> (u16, u16) {
-        let (width, height) = self.display.get_size();
+    /// Adds a model drawn with `shader`, failing if the shader's attributes do not have a
+    /// value for every vertex of the model, if its functions use handles from another
+    /// layout, or if its vertex function returns a position which is not finite or leaves
+    /// a varying unset. Both functions run once on the first vertex to find out.
+    pub fn add_model(
+        &mut self,
+        model: Model,
+        shader: Arc<RwLock<Shader>>,
+    ) -> Result<Arc<RwLock<Model>>, RangleError> {
+        self.add_model_with_stencil(model, shader, StencilState::default())
+    }
 
-        let fx = (((-x + 1.0) / 2.0) * (width - 1) as f32).round() as u16;
-        let fy = (((-y + 1.0) / 2.0) * (height - 1) as f32).round() as u16;
+    /// Adds a model which is stencil tested as it is rasterized. Models are drawn in the
+    /// order they are added, so earlier models can mark the stencil buffer to mask, cut
+    /// away or outline later ones. Sorted transparency reorders surfaces by depth instead,
+    /// and vector output ignores the stencil state.
+    pub fn add_model_with_stencil(
+        &mut self,
+        model: Model,
+        shader: Arc<RwLock<Shader>>,
+        stencil: StencilState,
+    ) -> Result<Arc<RwLock<Model>>, RangleError> {
+        shader.read().unwrap().check(model.vertex_indices.len())?;
 
-        (
-            fx.max(0).min(width-1),
-            fy.max(0).min(height-1)
-        )
+        let model = Arc::new(RwLock::new(model));
+
+        self.models.push((model.clone(), shader, stencil));
+
+        Ok(model)
     }
 
-    fn framebuffer_draw_point(&mut self, x: u16, y: u16, val: Vec4) {
-        let (width, height) = self.display.get_size();
-        if x >= width || y >= height {
-            return;
+    /// Draws every model, failing if a model changed since it was added so that its
+    /// shader's attributes no longer have a value for every vertex.
+    pub fn render_scene(&mut self) -> Result<(), RangleError> {
+        for (model, shader, _) in &self.models {
+            shader
+                .read()
+                .unwrap()
+                .check_attributes(model.read().unwrap().vertex_indices.len())?;
         }
 
-        let (x, y) = (x as usize, y as usize);
+        if let RangleOutput::Vector = self.output {
+            if let RangleMode::SolidWireframe | RangleMode::HiddenLine = self.display_mode {
+                return Err(RangleError::VectorHiddenLines);
+            }
 
-        let color_vec = (val
-            .max(Vec4::new(0.0, 0.0, 0.0, 0.0))
-            .min(Vec4::new(1.0, 1.0, 1.0, 1.0))
-            * 255.0)
-            .round();
+            let primitives = self.collect_primitives();
 
-        let color = (
-            color_vec.x as u8,
-            color_vec.y as u8,
-            color_vec.z as u8,
-            color_vec.w as u8,
-        );
+            return self.display.draw_primitives(&primitives);
+        }
+
+        self.render_tiles();
+
+        self.display.draw_buffer(&self.frame_buffer)?;
+
+        Ok(())
+    }
+}
 
-        self.frame_buffer.draw_point(x, y, color);
+#[cfg(test)]
+mod tests {
+    use std::{
+        env, fs,
+        ops::Deref,
+        panic::{self, AssertUnwindSafe},
+        path::{Path, PathBuf},
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc, RwLock,
+        },
+    };
+
+    use glam::{Mat2, Vec2, Vec3, Vec4};
+
+    use crate::{
+        rangle_display::Color,
+        raster::{Paint, RasterPrimitive, RasterVertex, Tile},
+        AntiAliasing, BlendMode, CullMode, DepthCompare, DepthState, FrameBuffer, FrontFace,
+        Interpolation, LineCap, Model, PointShape, Rangle, RangleDisplay, RangleError, RangleMode,
+        Shader, ShaderLayout, StencilOp, StencilState, SubMeshKind, Transparency, Varyings,
+    };
+
+    /// A display which only remembers its size, for tests which inspect `Rangle` directly.
+    struct TestDisplay {
+        width: u16,
+        height: u16,
     }
 
-    fn render_point(&mut self, p: Vec2, val: ShaderTypeMap, shader: &Shader) {
-        let (width, _height) = self.get_size();
-        let width = width as usize;
-        let (fx, fy) = self.transform_coords_normalized_to_framebuffer(p.x, p.y);
+    impl RangleDisplay for TestDisplay {
+        fn get_size(&self) -> (u16, u16) {
+            (self.width, self.height)
+        }
 
-        let z = match val.get("rangle_Position") {
-            Some(&ShaderType::Vec4(v)) => -v.z / v.w,
-            _ => panic!()
-        };
+        fn get_background_color(&self) -> Color {
+            (0, 0, 0, 255)
+        }
 
-        let color;
-        let index = fy as usize * width + fx as usize;
-        if z >= -1.0 && z <= 1.0 && z > self.depth_buffer[index] {
-            self.depth_buffer[index] = z;
-            color = shader.run_fragment(val);
+        fn set_background_color(&mut self, _color: Color) {}
 
-            self.framebuffer_draw_point(fx, fy, color);
+        fn draw_buffer(&self, _frame_buffer: &FrameBuffer) -> Result<(), RangleError> {
+            Ok(())
         }
     }
 
-    fn render_line(
-        &mut self,
-        p1: Vec2,
-        p2: Vec2,
-        val1: ShaderTypeMap,
-        val2: ShaderTypeMap,
-        shader: &Shader,
-    ) {
-        let (mut x1, mut y1) = (p1.x, p1.y);
-        let (mut x2, mut y2) = (p2.x, p2.y);
+    /// A `Rangle` drawing to a `TestDisplay` of the given size.
+    fn test_rangle(width: u16, height: u16) -> Rangle {
+        Rangle::new(Box::new(TestDisplay { width, height })).unwrap()
+    }
 
-        let (mut val1, mut val2) = (val1, val2);
+    /// A shader which keeps the position it is given and draws every fragment white, for
+    /// tests which build the varyings of `layout` themselves.
+    fn pass_through_shader(layout: ShaderLayout) -> Arc<RwLock<Shader>> {
+        Shader::new(layout, |val, _| val.position(), |_, _| Some(Vec4::ONE))
+    }
 
-        let xd = (x1 - x2).abs();
-        let yd = (y1 - y2).abs();
+    /// A shader which takes the model's vertices as clip space positions, and passes them
+    /// on to `fragment` interpolated.
+    fn clip_space_shader(
+        model: &Model,
+        fragment: impl Fn(Vec3) -> Option<Vec4> + Send + Sync + 'static,
+    ) -> Arc<RwLock<Shader>> {
+        let mut layout = ShaderLayout::new();
+        let position = layout
+            .add_attribute("position", model.get_vertex_buffer())
+            .unwrap();
+
+        Shader::new(
+            layout,
+            move |val, _| Vec4::from((val.get(position), 1.0)),
+            move |val, _| fragment(val.get(position)),
+        )
+    }
 
-        let (width, _height) = self.get_size();
-        let width = width as usize;
+    /// A file in the temp directory, removed once the test is done with it.
+    struct TempFile(PathBuf);
 
-        let mut k = 0.0;
-        let mut index;
-        let mut color;
-        let mut interpolated_val;
+    impl Deref for TempFile {
+        type Target = Path;
 
-        if xd >= yd {
-            if x2 > x1 {
-                let temp = x1;
-                x1 = x2;
-                x2 = temp;
+        fn deref(&self) -> &Path {
+            &self.0
+        }
+    }
 
-                let temp = y1;
-                y1 = y2;
-                y2 = temp;
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
 
-                let temp = val1;
-                val1 = val2;
-                val2 = temp;
-            }
+    fn write_temp_file(name: &str, contents: &[u8]) -> TempFile {
+        let path = env::temp_dir().join(format!("rangle-test-{}-{}", std::process::id(), name));
+        fs::write(&path, contents).unwrap();
 
-            let (fx1, fy1) = self.transform_coords_normalized_to_framebuffer(x1, y1);
-            let (fx2, fy2) = self.transform_coords_normalized_to_framebuffer(x2, y2);
+        TempFile(path)
+    }
 
-            let fxd = (fx2 - fx1) as f32;
-            let fyd = fy2 as f32 - fy1 as f32;
+    #[test]
+    #[allow(clippy::assertions_on_constants)]
+    fn it_works() {
+        assert!(true);
+    }
+
+    #[test]
+    fn obj_reads_tex_coords_normals_and_relative_indices() {
+        let path = write_temp_file(
+            "relative.obj",
+            b"v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nvt 0 0\nvt 1 1\nvn 0 0 1\n\
+              f -4/1/1 -3/2/1 -2//-1 -1\n",
+        );
+        let model = Model::from_file(path.to_str().unwrap()).unwrap();
+
+        assert_eq!(model.vertex_indices, vec![0, 1, 2, 0, 2, 3]);
+        assert_eq!(
+            model.get_tex_coord_buffer(),
+            vec![
+                Vec2::ZERO,
+                Vec2::ONE,
+                Vec2::ZERO,
+                Vec2::ZERO,
+                Vec2::ZERO,
+                Vec2::ZERO
+            ]
+        );
+        assert_eq!(model.get_normal_buffer()[2], Vec3::Z);
+        assert_eq!(model.get_normal_buffer()[5], Vec3::ZERO);
+    }
 
-            let dist_jump = 1.0 / fxd;
-            let y_jump = fyd / fxd;
+    #[test]
+    fn obj_assigns_materials_from_mtllib() {
+        let mtl = write_temp_file(
+            "materials.mtl",
+            b"newmtl Red\nKd 1 0 0\nd 0.5\nmap_Kd -s 1 1 1 red.png\nnewmtl Green\nKd 0 1 0\n",
+        );
+        let obj = write_temp_file(
+            "materials.obj",
+            format!(
+                "mtllib {}\nv 0 0 0\nv 1 0 0\nv 1 1 0\nf 1 2 3\nusemtl Red\nf 1 2 3\n\
+                 usemtl Green\nf 1 2 3\nusemtl Missing\nf 1 2 3\n",
+                mtl.file_name().unwrap().to_str().unwrap()
+            )
+            .as_bytes(),
+        );
+        let model = Model::from_file(obj.to_str().unwrap()).unwrap();
 
-            let mut fy = fy1 as f32;
-            for fx in fx1..=fx2 {
-                interpolated_val = Self::interpolate_line(&val1, &val2, k);
-                let z = match interpolated_val.get("rangle_Position") {
-                    Some(&ShaderType::Vec4(v)) => -v.z / v.w,
-                    _ => panic!()
-                };
+        assert_eq!(model.get_face_materials(), &[None, Some(0), Some(1), None]);
+        assert_eq!(
+            model.get_materials()[0].diffuse_map.as_deref(),
+            Some("red.png")
+        );
+        assert_eq!(model.get_materials()[1].specular, Vec3::ZERO);
+
+        let colors = model.get_material_color_buffer(Vec4::ONE);
+        assert_eq!(colors[0], Vec4::ONE);
+        assert_eq!(colors[3], Vec4::new(1.0, 0.0, 0.0, 0.5));
+        assert_eq!(colors[6], Vec4::new(0.0, 1.0, 0.0, 1.0));
+
+        // The face without a material after the others is exported with a default one.
+        let out = write_temp_file("materials-out.obj", b"");
+        let out_mtl = write_temp_file("materials-out.mtl", b"");
+        model.write_obj(out.to_str().unwrap(), false).unwrap();
+        let exported = Model::from_file(out.to_str().unwrap()).unwrap();
+        assert!(!fs::read_to_string(&*out)
+            .unwrap()
+            .lines()
+            .any(|line| line.trim() == "usemtl"));
+        assert!(fs::read_to_string(&*out_mtl)
+            .unwrap()
+            .contains("newmtl default"));
+        assert_eq!(
+            exported.get_face_materials(),
+            &[None, Some(0), Some(1), Some(2)]
+        );
+        assert_eq!(exported.get_materials()[2].name, "default");
+    }
 
-                index = fy as usize * width + fx as usize;
-                if z >= -1.0 && z <= 1.0 && z > self.depth_buffer[index] {
-                    self.depth_buffer[index] = z;
-                    color = shader.run_fragment(interpolated_val);
+    #[test]
+    fn obj_sub_meshes_can_be_selected() {
+        let path = write_temp_file(
+            "sub_meshes.obj",
+            b"v 0 0 0\nv 1 0 0\nv 1 1 0\no Body\nf 1 2 3\ng Left Both\nf 1 2 3\n\
+              g Right Both\nf 1 2 3\nf 1 2 3\no Tail\nf 1 2 3\n",
+        );
+        let model = Model::from_file(path.to_str().unwrap()).unwrap();
+
+        let names: Vec<(&str, SubMeshKind)> = model
+            .get_sub_meshes()
+            .iter()
+            .map(|m| (m.name.as_str(), m.kind))
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                ("Body", SubMeshKind::Object),
+                ("Left", SubMeshKind::Group),
+                ("Both", SubMeshKind::Group),
+                ("Right", SubMeshKind::Group),
+                ("Both", SubMeshKind::Group),
+                ("Tail", SubMeshKind::Object),
+            ]
+        );
 
-                    self.framebuffer_draw_point(fx, fy.round() as u16, color);
-                }
+        let mut selected = model.clone();
+        selected.retain_sub_meshes(&["Right"]).unwrap();
+        assert_eq!(selected.vertex_indices.len(), 6);
+        assert_eq!(selected.get_sub_meshes()[0].indices, 0..6);
+
+        let mut hidden = model.clone();
+        hidden.remove_sub_meshes(&["Both"]).unwrap();
+        assert_eq!(hidden.vertex_indices.len(), 6);
+        assert_eq!(hidden.get_sub_meshes()[0].indices, 0..3);
+        assert_eq!(hidden.get_sub_meshes()[1].indices, 3..6);
+
+        assert!(matches!(
+            model.clone().retain_sub_meshes(&["Nope"]),
+            Err(RangleError::UnknownSubMesh(_))
+        ));
+    }
 
-                fy += y_jump;
-                k += dist_jump;
-            }
-        } else {
-            if y2 > y1 {
-                let temp = x1;
-                x1 = x2;
-                x2 = temp;
-
-                let temp = y1;
-                y1 = y2;
-                y2 = temp;
-
-                let temp = val1;
-                val1 = val2;
-                val2 = temp;
+    #[test]
+    fn stl_merges_duplicate_vertices() {
+        let ascii = write_temp_file(
+            "square.stl",
+            b"solid square\n\
+              facet normal 0 0 1\n outer loop\n  vertex 0 0 0\n  vertex 1 0 0\n  vertex 1 1 0\n\
+              endloop\n endfacet\n\
+              facet normal 0 0 1\n outer loop\n  vertex 0 0 0\n  vertex 1 1 0\n  vertex 0 1 -0\n\
+              endloop\n endfacet\n\
+              endsolid square\n",
+        );
+        let model = Model::from_stl(ascii.to_str().unwrap()).unwrap();
+
+        assert_eq!(model.vertices.len(), 4 * 3);
+        assert_eq!(model.vertex_indices, vec![0, 1, 2, 0, 2, 3]);
+        assert_eq!(model.get_normal_buffer(), vec![Vec3::Z; 6]);
+
+        let mut bytes = b"solid but actually binary".to_vec();
+        bytes.resize(80, 0);
+        bytes.extend_from_slice(&2u32.to_le_bytes());
+        let triangles: [[f32; 12]; 2] = [
+            [0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 1.0, 1.0, 0.0],
+            [0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 1.0, 0.0, 0.0, 1.0, 0.0],
+        ];
+        for triangle in &triangles {
+            for v in triangle {
+                bytes.extend_from_slice(&v.to_le_bytes());
             }
+            bytes.extend_from_slice(&[0, 0]);
+        }
+        let binary = write_temp_file("square-binary.stl", &bytes);
+        let binary_model = Model::from_stl(binary.to_str().unwrap()).unwrap();
 
-            let (fx1, fy1) = self.transform_coords_normalized_to_framebuffer(x1, y1);
-            let (fx2, fy2) = self.transform_coords_normalized_to_framebuffer(x2, y2);
+        assert_eq!(binary_model.vertices, model.vertices);
+        assert_eq!(binary_model.vertex_indices, model.vertex_indices);
+    }
 
-            let fxd = fx2 as f32 - fx1 as f32;
-            let fyd = (fy2 - fy1) as f32;
+    #[test]
+    fn ply_reads_vertex_colors() {
+        let ascii = write_temp_file(
+            "colors.ply",
+            b"ply\nformat ascii 1.0\ncomment made by hand\nelement vertex 4\n\
+              property float x\nproperty float y\nproperty float z\n\
+              property uchar red\nproperty uchar green\nproperty uchar blue\n\
+              element face 1\nproperty list uchar int vertex_indices\nend_header\n\
+              0 0 0 255 0 0\n1 0 0 0 255 0\n1 1 0 0 0 255\n0 1 0 255 255 255\n4 0 1 2 3\n",
+        );
+        let model = Model::from_ply(ascii.to_str().unwrap()).unwrap();
 
-            let dist_jump = 1.0 / fyd;
-            let x_jump = fxd / fyd;
+        assert_eq!(model.vertex_indices, vec![0, 1, 2, 0, 2, 3]);
+        assert_eq!(
+            model.get_color_buffer(Vec4::ZERO)[1],
+            Vec4::new(0.0, 1.0, 0.0, 1.0)
+        );
 
-            let mut fx = fx1 as f32;
-            for fy in fy1..=fy2 {
-                interpolated_val = Self::interpolate_line(&val1, &val2, k);
-                let z = match interpolated_val.get("rangle_Position") {
-                    Some(&ShaderType::Vec4(v)) => -v.z / v.w,
-                    _ => panic!()
-                };
+        let mut bytes = b"ply\nformat binary_big_endian 1.0\nelement vertex 3\n\
+                          property double x\nproperty double y\nproperty double z\n\
+                          element face 1\nproperty list uchar uint vertex_index\nend_header\n"
+            .to_vec();
+        for v in &[0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 1.0, 1.0, 0.0f64] {
+            bytes.extend_from_slice(&v.to_be_bytes());
+        }
+        bytes.push(3);
+        for i in &[0u32, 1, 2] {
+            bytes.extend_from_slice(&i.to_be_bytes());
+        }
+        let binary = write_temp_file("binary.ply", &bytes);
+        let model = Model::from_ply(binary.to_str().unwrap()).unwrap();
 
-                index = fy as usize * width + fx as usize;
-                if z >= -1.0 && z <= 1.0 && z > self.depth_buffer[index] {
-                    self.depth_buffer[index] = z;
-                    color = shader.run_fragment(interpolated_val);
+        assert_eq!(
+            model.vertices,
+            vec![0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 1.0, 1.0, 0.0]
+        );
+        assert_eq!(model.vertex_indices, vec![0, 1, 2]);
+        assert!(!model.has_vertex_colors());
+    }
 
-                    self.framebuffer_draw_point(fx.round() as u16, fy, color);
-                }
+    #[test]
+    fn models_round_trip_through_every_format() {
+        let path = write_temp_file(
+            "export.obj",
+            b"v 0 0 0\nv 2 0 0\nv 2 2 0\nv 0 2 0\nvn 0 0 1\no Square\nf 1//1 2//1 3//1 4//1\n",
+        );
+        let mut model = Model::from_file(path.to_str().unwrap()).unwrap();
+        model.translate(0.0, 0.0, 5.0);
+
+        let obj = write_temp_file("export-out.obj", b"");
+        model.write_obj(obj.to_str().unwrap(), true).unwrap();
+        let from_obj = Model::from_file(obj.to_str().unwrap()).unwrap();
+        assert_eq!(from_obj.vertices[..3], [-1.0, -1.0, 5.0]);
+        assert_eq!(from_obj.vertex_indices, model.vertex_indices);
+        assert_eq!(from_obj.get_normal_buffer(), model.get_normal_buffer());
+        assert_eq!(from_obj.get_sub_meshes()[0].name, "Square");
+
+        let stl = write_temp_file("export-out.stl", b"");
+        model.write_stl(stl.to_str().unwrap(), false).unwrap();
+        let from_stl = Model::from_stl(stl.to_str().unwrap()).unwrap();
+        assert_eq!(from_stl.vertices, model.vertices);
+        assert_eq!(from_stl.vertex_indices, model.vertex_indices);
+
+        let ply = write_temp_file("export-out.ply", b"");
+        model.write_ply(ply.to_str().unwrap(), false).unwrap();
+        let from_ply = Model::from_ply(ply.to_str().unwrap()).unwrap();
+        assert_eq!(from_ply.vertices, model.vertices);
+        assert_eq!(from_ply.vertex_indices, model.vertex_indices);
+    }
+
+    #[test]
+    fn vertex_normals_are_split_at_creases() {
+        // Two faces of a cube meeting at a right angle along the edge from (0, 0, 0) to (0, 1, 0).
+        let mut model = Model::from_vectors(
+            vec![
+                0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 1.0, 1.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, -1.0, 0.0,
+                1.0, -1.0,
+            ],
+            vec![0, 1, 2, 0, 2, 3, 0, 3, 5, 0, 5, 4],
+        );
+
+        model.compute_vertex_normals(30.0_f32.to_radians());
+        let normals = model.get_normal_buffer();
+        assert!(normals[..6].iter().all(|n| (*n - Vec3::Z).length() < 1e-6));
+        assert!(normals[6..].iter().all(|n| (*n + Vec3::X).length() < 1e-6));
+
+        model.compute_vertex_normals(180.0_f32.to_radians());
+        let normals = model.get_normal_buffer();
+        let shared = (Vec3::Z - Vec3::X).normalize();
+        assert!((normals[0] - shared).length() < 1e-6);
+        assert!((normals[1] - Vec3::Z).length() < 1e-6);
+
+        // A degenerate triangle sharing a corner leaves its neighbours' normals alone.
+        let mut model = Model::from_vectors(
+            vec![0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 1.0, 1.0, 0.0],
+            vec![0, 1, 2, 0, 0, 1],
+        );
+        model.compute_vertex_normals(180.0_f32.to_radians());
+        let normals = model.get_normal_buffer();
+        assert!(normals.iter().all(|n| (*n - Vec3::Z).length() < 1e-6));
+    }
+
+    #[test]
+    fn obj_reports_bad_tokens() {
+        let path = write_temp_file("bad.obj", b"v 0 0 0\nv 1 0 0\nv 1 1 zero\n");
 
-                fx += x_jump;
-                k += dist_jump;
+        match Model::from_file(path.to_str().unwrap()) {
+            Err(RangleError::ObjParse { line, token, .. }) => {
+                assert_eq!(line, 3);
+                assert_eq!(token, "zero");
             }
+            _ => panic!("expected a parse error"),
         }
     }
 
-    fn render_triangle(
-        &mut self,
-        p1: Vec2,
-        val1: ShaderTypeMap,
-        p2: Vec2,
-        val2: ShaderTypeMap,
-        p3: Vec2,
-        val3: ShaderTypeMap,
-        shader: &Shader,
-    ) {
-        let (x1, y1) = (p1.x, p1.y);
-        let (x2, y2) = (p2.x, p2.y);
-        let (x3, y3) = (p3.x, p3.y);
-
-        let (fx1, fy1) = self.transform_coords_normalized_to_framebuffer(x1, y1);
-        let (fx2, fy2) = self.transform_coords_normalized_to_framebuffer(x2, y2);
-        let (fx3, fy3) = self.transform_coords_normalized_to_framebuffer(x3, y3);
-
-        let fx_min = fx1.min(fx2).min(fx3) as u16;
-        let fx_max = fx1.max(fx2).max(fx3) as u16;
-        let fy_min = fy1.min(fy2).min(fy3) as u16;
-        let fy_max = fy1.max(fy2).max(fy3) as u16;
-
-        let (width, _height) = self.get_size();
-        let width = width as usize;
-
-        let mut color;
-        let mut interpolated_val;
-        let mut index;
-        for fy in fy_min..=fy_max {
-            for fx in fx_min..=fx_max {
-                let (a, b, c) = Self::transform_coords_framebuffer_to_barycentric(
-                    fx, fy, fx1, fy1, fx2, fy2, fx3, fy3,
-                );
+    #[test]
+    fn bsp_splits_intersecting_polygons_and_sorts_back_to_front() {
+        use crate::bsp::{BspTree, Polygon};
+        use glam::DVec3;
+
+        let near = Polygon {
+            points: vec![
+                DVec3::new(0.0, 0.0, 1.0),
+                DVec3::new(4.0, 0.0, 1.0),
+                DVec3::new(0.0, 4.0, 1.0),
+            ],
+            color: (255, 0, 0, 255),
+        };
+        let far = Polygon {
+            points: vec![
+                DVec3::new(0.0, 0.0, 5.0),
+                DVec3::new(4.0, 0.0, 5.0),
+                DVec3::new(0.0, 4.0, 5.0),
+            ],
+            color: (0, 255, 0, 255),
+        };
+        // Crosses both of the others, reaching from in front of `near` to behind `far`.
+        let crossing = Polygon {
+            points: vec![
+                DVec3::new(1.0, 0.0, 0.0),
+                DVec3::new(1.0, 4.0, 0.0),
+                DVec3::new(1.0, 0.0, 6.0),
+            ],
+            color: (0, 0, 255, 255),
+        };
 
-                if a >= 0.0 && a <= 1.0 && b >= 0.0 && b <= 1.0 && c >= 0.0 && c <= 1.0 {
-                    interpolated_val = Self::interpolate_triangle(a, b, c, &val1, &val2, &val3);
-                    let z = match interpolated_val.get("rangle_Position") {
-                        Some(&ShaderType::Vec4(v)) => -v.z / v.w,
-                        _ => panic!()
-                    };
+        let sorted = BspTree::new(vec![near, far.clone(), crossing]).into_back_to_front(DVec3::Z);
+        let colors: Vec<_> = sorted.iter().map(|p| p.color).collect();
 
-                    index = fy as usize * width + fx as usize;
-                    if z >= -1.0 && z <= 1.0 && z > self.depth_buffer[index] {
-                        self.depth_buffer[index] = z;
-                        color = shader.run_fragment(interpolated_val);
+        assert!(sorted.len() > 3);
+        assert_eq!(colors.first(), Some(&(0, 0, 255, 255)));
+        assert_eq!(colors.last(), Some(&(0, 0, 255, 255)));
+        let position = |color| colors.iter().position(|&c| c == color).unwrap();
+        assert!(position(far.color) < position((255, 0, 0, 255)));
+    }
 
-                        self.framebuffer_draw_point(fx, fy, color);
-                    }
-                }
+    #[test]
+    fn clipping_splits_triangles_at_the_near_plane() {
+        use crate::clip;
+
+        let mut layout = ShaderLayout::new();
+        let color = layout.add_varying::<Vec3>("color").unwrap();
+        let shader = pass_through_shader(layout);
+        let shader = shader.read().unwrap();
+
+        let vertex = |position: Vec4, value: Vec3| {
+            let mut val = shader.new_varyings(position);
+            val.set(color, value);
+
+            (position, val)
+        };
+
+        // The first corner is behind the near plane, where z < 0.
+        let polygon = clip::clip_polygon(
+            vec![
+                vertex(Vec4::new(0.0, 0.0, -1.0, 1.0), Vec3::X),
+                vertex(Vec4::new(0.5, 0.0, 1.0, 1.0), Vec3::Y),
+                vertex(Vec4::new(0.0, 0.5, 1.0, 1.0), Vec3::Z),
+            ],
+            &shader,
+        );
+
+        assert_eq!(polygon.len(), 4);
+        assert!(polygon.iter().all(|(p, _)| clip::is_inside(*p)));
+        assert!((polygon[0].1.get(color) - Vec3::new(0.5, 0.5, 0.0)).length() < 1e-6);
+
+        let behind = clip::clip_line(
+            vertex(Vec4::new(0.0, 0.0, -1.0, 1.0), Vec3::X),
+            vertex(Vec4::new(0.0, 0.0, -0.5, 1.0), Vec3::Y),
+            &shader,
+        );
+        assert!(behind.is_none());
+    }
+
+    #[test]
+    fn varyings_follow_their_interpolation_qualifiers() {
+        let mut layout = ShaderLayout::new();
+        let varyings = [
+            layout.add_varying::<Vec3>("perspective").unwrap(),
+            layout
+                .add_varying_with_interpolation("noperspective", Interpolation::NoPerspective)
+                .unwrap(),
+            layout
+                .add_varying_with_interpolation("flat", Interpolation::Flat)
+                .unwrap(),
+        ];
+        let shader = pass_through_shader(layout);
+        let shader = shader.read().unwrap();
+
+        let vertex = |w: f32, value: f32| {
+            let mut val = shader.new_varyings(Vec4::new(0.0, 0.0, 0.0, w));
+            for &varying in &varyings {
+                val.set(varying, Vec3::splat(value));
             }
-        }
+
+            val
+        };
+
+        // Halfway across the screen is only a quarter of the way in clip space, since the
+        // second vertex is three times as far away.
+        let mut val = Varyings::default();
+        Rangle::interpolate_line(&vertex(1.0, 0.0), &vertex(3.0, 1.0), 0.5, &shader, &mut val);
+        let [perspective, noperspective, flat] = varyings;
+
+        assert!((val.get(perspective).x - 0.25).abs() < 1e-6);
+        assert!((val.get(noperspective).x - 0.5).abs() < 1e-6);
+        assert_eq!(val.get(flat).x, 0.0);
     }
 
-    fn render_model(&mut self, model: &Model, shader: &Shader) {
-        for i in (0..model.vertex_indices.len()).step_by(3) {
-            let val1 = shader.run_vertex(i);
-            let val2 = shader.run_vertex(i + 1);
-            let val3 = shader.run_vertex(i + 2);
+    #[test]
+    fn culling_follows_winding_order() {
+        let mut rangle = test_rangle(4, 4);
 
-            let p1 = match val1.get("rangle_Position") {
-                Some(&ShaderType::Vec4(v)) => v / v.w,
-                _ => panic!("stop it"),
-            };
-            let p2 = match val2.get("rangle_Position") {
-                Some(&ShaderType::Vec4(v)) => v / v.w,
-                _ => panic!("stop it"),
-            };
-            let p3 = match val3.get("rangle_Position") {
-                Some(&ShaderType::Vec4(v)) => v / v.w,
-                _ => panic!("stop it"),
-            };
+        let (p1, p2, p3) = (
+            Vec4::new(0.0, 0.0, 0.5, 1.0),
+            Vec4::new(1.0, 0.0, 0.5, 1.0),
+            Vec4::new(0.0, 1.0, 0.5, 1.0),
+        );
+        assert!(!rangle.pipeline_state().is_culled(p1, p3, p2));
 
-            match self.display_mode {
-                RangleMode::Triangles => {
-                    self.render_triangle(p1.xy(), val1, p2.xy(), val2, p3.xy(), val3, shader);
-                },
-                RangleMode::Lines => {
-                    self.render_line(p1.xy(), p2.xy(), val1.clone(), val2.clone(), shader);
-                    self.render_line(p2.xy(), p3.xy(), val2, val3.clone(), shader);
-                    self.render_line(p3.xy(), p1.xy(), val3, val1, shader);
-                },
-                RangleMode::Points => {
-                    self.render_point(p1.xy(), val1, shader);
-                    self.render_point(p2.xy(), val2, shader);
-                    self.render_point(p3.xy(), val3, shader);
+        rangle.set_cull_mode(CullMode::Back);
+        assert!(!rangle.pipeline_state().is_culled(p1, p2, p3));
+        assert!(rangle.pipeline_state().is_culled(p1, p3, p2));
+
+        // Only the screen positions matter, not how far away each corner is.
+        assert!(!rangle.pipeline_state().is_culled(p1 * 3.0, p2, p3 * 2.0));
+
+        rangle.set_front_face(FrontFace::Clockwise);
+        assert!(rangle.pipeline_state().is_culled(p1, p2, p3));
+
+        rangle.set_cull_mode(CullMode::Front);
+        assert!(!rangle.pipeline_state().is_culled(p1, p2, p3));
+    }
+
+    #[test]
+    fn triangles_sharing_an_edge_cover_each_pixel_once() {
+        let shader = pass_through_shader(ShaderLayout::new());
+        let shader = shader.read().unwrap();
+
+        let corners = [
+            Vec2::new(0.0, 0.0),
+            Vec2::new(15.0, 5.6),
+            Vec2::new(12.6, 15.0),
+            Vec2::new(3.2, 13.4),
+        ];
+        let vertices: Vec<_> = corners
+            .iter()
+            .map(|&position| {
+                let val = shader.new_varyings(Vec4::new(0.0, 0.0, 0.5, 1.0));
+
+                RasterVertex {
+                    position,
+                    depth: -0.5,
+                    val,
+                }
+            })
+            .collect();
+
+        let rangle = test_rangle(16, 16);
+        let state = rangle.pipeline_state();
+
+        let coverage = |i: usize, j: usize, k: usize| {
+            let mut tile = Tile::new(0, 0, 16, 16, (0, 0, 0, 0), &state);
+            tile.draw(
+                &RasterPrimitive::Triangle([
+                    vertices[i].clone(),
+                    vertices[j].clone(),
+                    vertices[k].clone(),
+                ]),
+                &Paint::Shaded(&shader),
+            );
+
+            tile.colors
+                .iter()
+                .map(|color| color.3 != 0)
+                .collect::<Vec<_>>()
+        };
+
+        let (first, second) = (coverage(0, 1, 2), coverage(0, 2, 3));
+        let quad = coverage(0, 1, 3);
+        let rest = coverage(1, 2, 3);
+
+        // Both ways of splitting the quad cover the same pixels, each exactly once.
+        for i in 0..first.len() {
+            assert!(!(first[i] && second[i]));
+            assert!(!(quad[i] && rest[i]));
+            assert_eq!(first[i] || second[i], quad[i] || rest[i]);
+        }
+        assert!(first.iter().filter(|&&v| v).count() > 0);
+    }
+
+    #[test]
+    fn multisampling_blends_pixels_along_edges() {
+        let shader = pass_through_shader(ShaderLayout::new());
+        let shader = shader.read().unwrap();
+
+        let mut rangle = test_rangle(8, 8);
+        assert!(matches!(
+            rangle.set_anti_aliasing(AntiAliasing::Msaa { samples: 3 }),
+            Err(RangleError::InvalidSampleCount(3))
+        ));
+        rangle
+            .set_anti_aliasing(AntiAliasing::Msaa { samples: 4 })
+            .unwrap();
+
+        // A vertical edge through the centers of the third column of pixels.
+        let vertices: Vec<_> = [(2.0, -20.0), (2.0, 40.0), (-30.0, 10.0)]
+            .iter()
+            .map(|&(x, y)| {
+                let val = shader.new_varyings(Vec4::new(0.0, 0.0, 0.0, 1.0));
+
+                RasterVertex {
+                    position: Vec2::new(x, y),
+                    depth: 0.0,
+                    val,
                 }
+            })
+            .collect();
+
+        let mut tile = Tile::new(0, 0, 8, 8, (0, 0, 0, 0), &rangle.pipeline_state());
+        tile.draw(
+            &RasterPrimitive::Triangle([
+                vertices[0].clone(),
+                vertices[1].clone(),
+                vertices[2].clone(),
+            ]),
+            &Paint::Shaded(&shader),
+        );
+        let tile = tile.resolve();
+
+        // Two of the four samples in the edge pixels fall inside.
+        let alpha: Vec<_> = (0..4).map(|x| tile.colors[4 * 8 + x].3).collect();
+        assert_eq!(alpha, vec![255, 255, 128, 0]);
+        assert_eq!(tile.depths[4 * 8 + 2], 0.0);
+    }
+
+    #[test]
+    fn wide_lines_follow_their_caps_and_smooth_lines_keep_their_weight() {
+        let shader = pass_through_shader(ShaderLayout::new());
+        let shader = shader.read().unwrap();
+
+        let vertex = |x: f32, y: f32| {
+            let val = shader.new_varyings(Vec4::new(0.0, 0.0, 0.0, 1.0));
+
+            RasterVertex {
+                position: Vec2::new(x, y),
+                depth: 0.0,
+                val,
             }
+        };
+
+        let mut rangle = test_rangle(16, 16);
+        let draw = |rangle: &Rangle, a: RasterVertex, b: RasterVertex| {
+            let mut tile = Tile::new(0, 0, 16, 16, (0, 0, 0, 0), &rangle.pipeline_state());
+            tile.draw(&RasterPrimitive::Line([a, b]), &Paint::Shaded(&shader));
+
+            tile.colors
+                .iter()
+                .map(|color| color.3 as u32)
+                .collect::<Vec<_>>()
+        };
+
+        // A line 4 pixels wide and 9 long, reaching 2 more at each end with square caps,
+        // and with round caps covering 3 more pixels before the line and 6 after it.
+        rangle.set_line_width(4.0);
+        for &(cap, pixels) in &[
+            (LineCap::Butt, 36),
+            (LineCap::Square, 52),
+            (LineCap::Round, 45),
+        ] {
+            rangle.set_line_cap(cap);
+
+            let covered = draw(&rangle, vertex(3.0, 8.0), vertex(12.0, 8.0));
+            assert_eq!(covered.iter().filter(|&&a| a == 255).count(), pixels);
+            assert_eq!(covered.iter().filter(|&&a| a != 0 && a != 255).count(), 0);
+        }
+
+        // Every column of a smoothed thin line adds up to one pixel between its two rows.
+        rangle.set_line_width(1.0);
+        rangle.set_smooth_lines(true);
+        let covered = draw(&rangle, vertex(1.0, 2.0), vertex(14.0, 9.5));
+        for x in 2..14 {
+            let column: u32 = (0..16).map(|y| covered[y * 16 + x]).sum();
+            assert!((254..=256).contains(&column));
         }
     }
 
-    pub fn add_model(&mut self, model: Model, shader: Rc<RefCell<Shader>>) -> Rc<RefCell<Model>> {
-        let model = Rc::new(RefCell::new(model));
+    #[test]
+    fn points_are_drawn_once_per_vertex_at_their_own_size() {
+        let shader = pass_through_shader(ShaderLayout::new());
+        let shader = shader.read().unwrap();
+
+        let mut rangle = test_rangle(17, 17);
+        rangle.set_point_size(2.0);
+
+        // Two triangles sharing an edge, with the last vertex asking to be larger. The
+        // corners land on pixel centers.
+        let corners = [
+            Vec4::new(-0.5, -0.5, 0.5, 1.0),
+            Vec4::new(0.5, -0.5, 0.5, 1.0),
+            Vec4::new(0.5, 0.5, 0.5, 1.0),
+            Vec4::new(-0.5, 0.5, 0.5, 1.0),
+        ];
+        let indices = [0, 1, 2, 0, 2, 3];
+        let vertices: Vec<_> = indices
+            .iter()
+            .map(|&i| {
+                let mut val = shader.new_varyings(corners[i]);
+                if i == 3 {
+                    val.set_point_size(5.0);
+                }
 
-        self.models.push((model.clone(), shader));
+                (corners[i], val)
+            })
+            .collect();
+
+        let points = rangle.pipeline_state().assemble_points(&indices, &vertices);
+        let sizes: Vec<_> = points
+            .iter()
+            .map(|point| match point {
+                RasterPrimitive::Point(_, size) => *size,
+                _ => panic!("expected a point"),
+            })
+            .collect();
+        assert_eq!(sizes, vec![2.0, 2.0, 2.0, 5.0]);
+
+        let coverage = |rangle: &Rangle| {
+            let mut tile = Tile::new(0, 0, 17, 17, (0, 0, 0, 0), &rangle.pipeline_state());
+            tile.draw(&points[3], &Paint::Shaded(&shader));
+
+            tile.colors.iter().filter(|color| color.3 != 0).count()
+        };
+        assert_eq!(coverage(&rangle), 25);
 
-        model
+        // A round sprite five pixels across loses the corners of the square.
+        rangle.set_point_shape(PointShape::Round);
+        assert_eq!(coverage(&rangle), 21);
     }
 
-    pub fn render_scene(&mut self) -> Result<(), RangleError> {
-        let color = self.display.get_background_color();
+    #[test]
+    fn hidden_line_modes_draw_only_visible_edges() {
+        // A far square split along its diagonal, behind a nearer triangle which covers the
+        // middle of that diagonal.
+        let render = |mode: RangleMode| {
+            let model = Model::from_vectors(
+                vec![
+                    -0.8, -0.8, 0.8, 0.8, -0.8, 0.8, 0.8, 0.8, 0.8, -0.8, 0.8, 0.8, -0.6, -0.3,
+                    0.2, 0.6, -0.3, 0.2, 0.0, 0.6, 0.2,
+                ],
+                vec![0, 1, 2, 0, 2, 3, 4, 5, 6],
+            );
+            let shader = clip_space_shader(&model, |_| Some(Vec4::ONE));
+
+            let mut rangle = test_rangle(17, 17);
+            rangle.set_display_mode(mode);
+            rangle.set_wireframe_color(Vec4::new(1.0, 0.0, 0.0, 1.0));
+            rangle.add_model(model, shader).unwrap();
+            rangle.render_scene().unwrap();
+
+            // The diagonal where it is visible, where it passes behind the triangle, and
+            // the triangle's bottom edge.
+            [(2, 2), (8, 8), (8, 10)]
+                .iter()
+                .map(|&(x, y)| rangle.frame_buffer.buffer[y * 17 + x])
+                .collect::<Vec<_>>()
+        };
 
-        let _ = self.depth_buffer.iter_mut().map(|v| *v = -2.0).count();
-        self.frame_buffer.fill_buffer(color);
+        let (black, white, red) = ((0, 0, 0, 255), (255, 255, 255, 255), (255, 0, 0, 255));
+        assert_eq!(render(RangleMode::Lines), vec![white, white, white]);
+        assert_eq!(render(RangleMode::HiddenLine), vec![white, black, white]);
+        assert_eq!(render(RangleMode::SolidWireframe), vec![red, white, red]);
+    }
 
-        let models = self.models.clone();
-        for (model, shader) in &models {
-            let model = model.borrow();
-            let shader = shader.borrow();
+    #[test]
+    fn depth_state_controls_testing_and_the_depth_buffer() {
+        // Two squares over the middle pixel, the nearer drawn first.
+        let render = |depth_state: DepthState| {
+            let model = Model::from_vectors(
+                vec![
+                    -0.5, -0.5, 0.25, 0.5, -0.5, 0.25, 0.5, 0.5, 0.25, -0.5, 0.5, 0.25, -0.5, -0.5,
+                    0.75, 0.5, -0.5, 0.75, 0.5, 0.5, 0.75, -0.5, 0.5, 0.75,
+                ],
+                vec![0, 1, 2, 0, 2, 3, 4, 5, 6, 4, 6, 7],
+            );
+            let shader = clip_space_shader(&model, |_| Some(Vec4::ONE));
+
+            let mut rangle = test_rangle(5, 5);
+            rangle.set_depth_state(depth_state);
+            rangle.add_model(model, shader).unwrap();
+            rangle.render_scene().unwrap();
+
+            let depths = rangle.get_depth_buffer();
+            (depths[2 * 5 + 2], depths[0])
+        };
 
-            self.render_model(&model, &shader);
-        }
+        assert_eq!(render(DepthState::default()), (0.25, 1.0));
+        assert_eq!(render(DepthState::reversed()), (0.75, 0.0));
+        assert_eq!(
+            render(DepthState {
+                compare: DepthCompare::Greater,
+                clear: 0.0,
+                ..DepthState::default()
+            }),
+            (0.75, 0.0)
+        );
+        assert_eq!(
+            render(DepthState {
+                range: (0.5, 1.0),
+                ..DepthState::default()
+            }),
+            (0.625, 1.0)
+        );
+        assert_eq!(
+            render(DepthState {
+                write: false,
+                ..DepthState::default()
+            }),
+            (1.0, 1.0)
+        );
+    }
 
-        self.display.draw_buffer(&self.frame_buffer)?;
+    #[test]
+    fn blend_modes_and_weighted_transparency_combine_colors() {
+        assert_eq!(
+            BlendMode::Alpha.blend((0, 0, 255, 255), (255, 0, 0, 51)),
+            (51, 0, 204, 255)
+        );
+        assert_eq!(
+            BlendMode::Additive.blend((100, 100, 100, 255), (255, 0, 0, 51)),
+            (151, 100, 100, 255)
+        );
+        assert_eq!(
+            BlendMode::Multiply.blend((200, 200, 200, 255), (0, 255, 0, 255)),
+            (0, 200, 0, 255)
+        );
+        assert!(!BlendMode::Replace.blends((255, 0, 0, 51)));
+        assert!(!BlendMode::Alpha.blends((255, 0, 0, 255)));
+        assert!(BlendMode::Alpha.blends((255, 0, 0, 51)));
+        assert!(BlendMode::Additive.blends((255, 0, 0, 255)));
+
+        let rangle = test_rangle(4, 4);
+        let mut state = rangle.pipeline_state();
+        state.transparency = Transparency::WeightedBlended;
+
+        // A triangle covering the whole tile at the given window depth.
+        let triangle = |depth: f32| {
+            let vertex = |x: f32, y: f32| RasterVertex {
+                position: Vec2::new(x, y),
+                depth,
+                val: Varyings::default(),
+            };
 
-        Ok(())
+            RasterPrimitive::Triangle([vertex(-1.0, -1.0), vertex(9.0, -1.0), vertex(-1.0, 9.0)])
+        };
+
+        let render = |layers: &[(f32, Color)]| {
+            let mut tile = Tile::new(0, 0, 4, 4, (0, 0, 0, 255), &state);
+            for &transparent_pass in &[false, true] {
+                tile.transparent_pass = transparent_pass;
+                for &(depth, color) in layers {
+                    tile.draw(&triangle(depth), &Paint::Flat(color));
+                }
+            }
+
+            tile.resolve().colors[5]
+        };
+
+        let opaque = (0.5, (0, 0, 255, 255));
+        let red = (0.2, (255, 0, 0, 128));
+        let green = (0.3, (0, 255, 0, 128));
+        let hidden = (0.8, (255, 255, 255, 128));
+
+        // Transparent fragments blend the same whichever order they are drawn in, and those
+        // behind opaque fragments are left out.
+        let color = render(&[red, green, opaque, hidden]);
+        assert_eq!(render(&[hidden, opaque, green, red]), color);
+        assert_eq!(render(&[opaque]), (0, 0, 255, 255));
+        assert!(color.0 > color.1 && color.1 > 0 && color.2 > 0);
+        assert_eq!(color.3, 255);
     }
-}
 
-#[cfg(test)]
-mod tests {
     #[test]
-    fn it_works() {
-        assert!(true);
+    fn stencil_tests_mask_models_and_fragments_can_be_discarded() {
+        // A square at depth z reaching half_size from the middle of the screen.
+        let square = |half_size: f32, z: f32| {
+            let (a, b) = (-half_size, half_size);
+            Model::from_vectors(
+                vec![a, a, z, b, a, z, b, b, z, a, b, z],
+                vec![0, 1, 2, 0, 2, 3],
+            )
+        };
+
+        let mut rangle = test_rangle(8, 8);
+
+        // The square marks the stencil buffer, and the nearer screen covering quad is only
+        // drawn outside it.
+        let inner = square(0.5, 0.5);
+        let shader = clip_space_shader(&inner, |_| Some(Vec4::ONE));
+        rangle
+            .add_model_with_stencil(
+                inner,
+                shader,
+                StencilState {
+                    reference: 1,
+                    pass: StencilOp::Replace,
+                    ..StencilState::default()
+                },
+            )
+            .unwrap();
+        let screen = square(1.0, 0.25);
+        let shader = clip_space_shader(&screen, |_| Some(Vec4::ONE));
+        rangle
+            .add_model_with_stencil(
+                screen,
+                shader,
+                StencilState {
+                    compare: DepthCompare::NotEqual,
+                    reference: 1,
+                    ..StencilState::default()
+                },
+            )
+            .unwrap();
+        rangle.render_scene().unwrap();
+
+        let depths = rangle.get_depth_buffer();
+        let stencils = rangle.get_stencil_buffer();
+        assert_eq!((depths[4 * 8 + 4], stencils[4 * 8 + 4]), (0.5, 1));
+        assert_eq!((depths[4 * 8 + 1], stencils[4 * 8 + 1]), (0.25, 0));
+
+        // Fragments with negative x, which land on the right of the screen, are discarded,
+        // leaving every buffer untouched.
+        let mut rangle = test_rangle(8, 8);
+        let screen = square(1.0, 0.25);
+        let shader = clip_space_shader(&screen, |position| {
+            if position.x > 0.0 {
+                Some(Vec4::ONE)
+            } else {
+                None
+            }
+        });
+        rangle
+            .add_model_with_stencil(
+                screen,
+                shader,
+                StencilState {
+                    pass: StencilOp::IncrementClamp,
+                    depth_fail: StencilOp::IncrementClamp,
+                    ..StencilState::default()
+                },
+            )
+            .unwrap();
+        rangle.render_scene().unwrap();
+
+        let depths = rangle.get_depth_buffer();
+        let stencils = rangle.get_stencil_buffer();
+        assert_eq!((depths[4 * 8 + 1], stencils[4 * 8 + 1]), (0.25, 1));
+        assert_eq!((depths[4 * 8 + 6], stencils[4 * 8 + 6]), (1.0, 0));
+        assert_eq!(rangle.frame_buffer.buffer[4 * 8 + 6], (0, 0, 0, 255));
+    }
+
+    #[test]
+    fn shaders_can_capture_their_parameters() {
+        let model = Model::from_vectors(
+            vec![
+                -2.0, -2.0, 0.5, 2.0, -2.0, 0.5, 2.0, 2.0, 0.5, -2.0, 2.0, 0.5,
+            ],
+            vec![0, 1, 2, 0, 2, 3],
+        );
+
+        let tint = Vec4::new(1.0, 0.5, 0.0, 1.0);
+        let shaded = Arc::new(AtomicUsize::new(0));
+        let counter = shaded.clone();
+        let shader = clip_space_shader(&model, move |_| {
+            counter.fetch_add(1, Ordering::Relaxed);
+
+            Some(tint)
+        });
+
+        let mut rangle = test_rangle(4, 4);
+        rangle.set_thread_count(2);
+        rangle.add_model(model, shader).unwrap();
+        // Adding the model ran the shader once to check it.
+        shaded.store(0, Ordering::Relaxed);
+        rangle.render_scene().unwrap();
+
+        // Each drawn pixel is shaded exactly once, by whichever thread draws its tile.
+        let drawn = rangle
+            .frame_buffer
+            .buffer
+            .iter()
+            .filter(|&&color| color == (255, 128, 0, 255))
+            .count();
+        assert!(drawn > 0);
+        assert_eq!(shaded.load(Ordering::Relaxed), drawn);
+    }
+
+    #[test]
+    fn shader_layouts_check_names_and_types_up_front() {
+        let mut layout = ShaderLayout::new();
+        let position = layout
+            .add_attribute("position", vec![Vec3::ZERO, Vec3::ONE])
+            .unwrap();
+        let rotation = layout.add_varying::<Mat2>("rotation").unwrap();
+        let scale = layout.add_uniform("scale", 2.0_f32).unwrap();
+
+        assert!(matches!(
+            layout.add_attribute("position", vec![0.0_f32; 2]),
+            Err(RangleError::DuplicateShaderAttributes)
+        ));
+        assert!(matches!(
+            layout.add_varying::<f32>("rotation"),
+            Err(RangleError::DuplicateShaderVaryings)
+        ));
+        assert!(matches!(
+            layout.add_uniform("scale", 1.0_f32),
+            Err(RangleError::DuplicateShaderUniforms)
+        ));
+        assert!(matches!(
+            layout.varying::<Vec4>("position"),
+            Err(RangleError::MismatchedShaderTypes {
+                name: "position",
+                expected: "Vec4",
+                found: "Vec3",
+            })
+        ));
+        assert!(matches!(
+            layout.uniform::<f32>("offset"),
+            Err(RangleError::UnknownShaderValue("offset"))
+        ));
+        assert!(layout.varying::<Vec3>("position").is_ok());
+
+        let shader = Shader::new(
+            layout,
+            move |val, uniforms| {
+                let position = val.get(position) * uniforms.get(scale);
+                val.set(rotation, Mat2::from_angle(position.x));
+
+                Vec4::from((position, 1.0))
+            },
+            |_, _| Some(Vec4::ONE),
+        );
+        let mut shader = shader.write().unwrap();
+        let scale = shader.uniform::<f32>("scale").unwrap();
+        shader.set_uniform(scale, 0.5).unwrap();
+
+        let foreign = ShaderLayout::new().add_uniform("scale", 1.0_f32).unwrap();
+        assert!(matches!(
+            shader.set_uniform(foreign, 1.0),
+            Err(RangleError::MismatchedShaderLayout)
+        ));
+
+        let (first, second) = (shader.run_vertex(0), shader.run_vertex(1));
+        assert_eq!(second.position(), Vec4::new(0.5, 0.5, 0.5, 1.0));
+
+        // Every type interpolates component by component, matrices included.
+        let val = shader.interpolate(&[&first, &second], &[0.5, 0.5], &[0.5, 0.5]);
+        let expected = (Mat2::IDENTITY + Mat2::from_angle(0.5)) * 0.5;
+        assert!(val.get(rotation).abs_diff_eq(&expected, 1e-6));
+
+        // Outside the check in `add_model`, reading through another layout's handle is a bug.
+        let foreign = ShaderLayout::new().add_varying::<Mat2>("rotation").unwrap();
+        let read = panic::catch_unwind(AssertUnwindSafe(|| val.get(foreign)));
+        assert!(read.is_err());
+    }
+
+    #[test]
+    fn shaders_are_validated_against_their_models() {
+        let path = write_temp_file(
+            "validated.obj",
+            b"v 0 0 0\nv 1 0 0\nv 0 1 0\ng a\nf 1 2 3\ng b\nf 1 3 2\n",
+        );
+        let load = || Model::from_file(path.to_str().unwrap()).unwrap();
+        let positions = load().get_vertex_buffer();
+
+        let mut rangle = test_rangle(4, 4);
+
+        let mut layout = ShaderLayout::new();
+        layout.add_attribute("position", positions.clone()).unwrap();
+        layout.add_attribute("color", vec![Vec4::ONE; 2]).unwrap();
+        let shader = Shader::new(layout, |_, _| Vec4::ZERO, |_, _| Some(Vec4::ONE));
+        assert!(matches!(
+            rangle.add_model(load(), shader),
+            Err(RangleError::MismatchedAttributeLength {
+                name: "color",
+                expected: 6,
+                found: 2,
+            })
+        ));
+
+        // The fragment shader reads a uniform through a handle from another layout.
+        let tint = ShaderLayout::new().add_uniform("tint", Vec4::ONE).unwrap();
+        let mut layout = ShaderLayout::new();
+        let position = layout.add_attribute("position", positions.clone()).unwrap();
+        let shader = Shader::new(
+            layout,
+            move |val, _| Vec4::from((val.get(position), 1.0)),
+            move |_, uniforms| Some(uniforms.get(tint)),
+        );
+        assert!(matches!(
+            rangle.add_model(load(), shader),
+            Err(RangleError::MismatchedShaderLayout)
+        ));
+
+        let mut layout = ShaderLayout::new();
+        let position = layout.add_attribute("position", positions.clone()).unwrap();
+        layout.add_varying::<Vec3>("normal").unwrap();
+        let shader = Shader::new(
+            layout,
+            move |val, _| Vec4::from((val.get(position), 1.0)),
+            |_, _| Some(Vec4::ONE),
+        );
+        assert!(matches!(
+            rangle.add_model(load(), shader),
+            Err(RangleError::UnsetShaderVarying("normal"))
+        ));
+
+        let shader = Shader::new(
+            ShaderLayout::new(),
+            |_, _| Vec4::splat(f32::NAN),
+            |_, _| Some(Vec4::ONE),
+        );
+        assert!(matches!(
+            rangle.add_model(load(), shader),
+            Err(RangleError::NonFiniteShaderPosition)
+        ));
+        assert!(rangle.models.is_empty());
+
+        // Dropping a sub-mesh after the model is added leaves the attributes too long.
+        let model = load();
+        let shader = clip_space_shader(&model, |_| Some(Vec4::ONE));
+        let model = rangle.add_model(model, shader).unwrap();
+        rangle.render_scene().unwrap();
+
+        model.write().unwrap().retain_sub_meshes(&["a"]).unwrap();
+        assert!(matches!(
+            rangle.render_scene(),
+            Err(RangleError::MismatchedAttributeLength {
+                name: "position",
+                expected: 3,
+                found: 6,
+            })
+        ));
     }
 }
@@ -0,0 +1,173 @@
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, Write},
+};
+
+use glam::{Vec3, Vec4};
+
+use crate::error::RangleError;
+
+/// A material as described by a Wavefront `.mtl` file.
+#[derive(Clone, Debug)]
+pub struct Material {
+    pub name: String,
+    /// `Ka`
+    pub ambient: Vec3,
+    /// `Kd`
+    pub diffuse: Vec3,
+    /// `Ks`
+    pub specular: Vec3,
+    /// `Ns`
+    pub shininess: f32,
+    /// `d`, or `1 - Tr`
+    pub dissolve: f32,
+    /// `illum`
+    pub illum: u32,
+    /// `map_Kd`
+    pub diffuse_map: Option<String>,
+}
+
+fn parse_error(filename: &str, line: usize, token: &str) -> RangleError {
+    RangleError::MtlParse {
+        filename: filename.to_string(),
+        line,
+        token: token.to_string(),
+    }
+}
+
+impl Material {
+    pub fn new(name: &str) -> Self {
+        Material {
+            name: name.to_string(),
+            ambient: Vec3::splat(0.2),
+            diffuse: Vec3::splat(0.8),
+            // Materials without `Ks` have no highlights.
+            specular: Vec3::ZERO,
+            shininess: 0.0,
+            dissolve: 1.0,
+            illum: 1,
+            diffuse_map: None,
+        }
+    }
+
+    pub fn from_file(filename: &str) -> Result<Vec<Self>, RangleError> {
+        let file = File::open(filename)?;
+
+        let mut materials: Vec<Material> = vec![];
+
+        let reader = BufReader::new(file);
+
+        for (line_number, line) in reader.lines().enumerate() {
+            let line = line?;
+            let line_number = line_number + 1;
+
+            let mut chunks = line.split_whitespace();
+            let keyword = match chunks.next() {
+                Some(v) => v,
+                None => continue,
+            };
+            let chunks: Vec<&str> = chunks.collect();
+
+            if keyword == "newmtl" {
+                if chunks.is_empty() {
+                    return Err(parse_error(filename, line_number, line.trim()));
+                }
+
+                materials.push(Material::new(&chunks.join(" ")));
+                continue;
+            }
+
+            if keyword.starts_with('#') {
+                continue;
+            }
+
+            let material = match materials.last_mut() {
+                Some(v) => v,
+                None => return Err(parse_error(filename, line_number, keyword)),
+            };
+
+            let parse_float = |i: usize| -> Result<f32, RangleError> {
+                let chunk = match chunks.get(i) {
+                    Some(&v) => v,
+                    None => return Err(parse_error(filename, line_number, line.trim())),
+                };
+
+                chunk
+                    .parse()
+                    .map_err(|_| parse_error(filename, line_number, chunk))
+            };
+
+            let parse_color = || -> Result<Vec3, RangleError> {
+                if chunks.first() == Some(&"spectral") || chunks.first() == Some(&"xyz") {
+                    return Err(parse_error(filename, line_number, chunks[0]));
+                }
+
+                let r = parse_float(0)?;
+                let g = if chunks.len() > 1 { parse_float(1)? } else { r };
+                let b = if chunks.len() > 2 { parse_float(2)? } else { r };
+
+                Ok(Vec3::new(r, g, b))
+            };
+
+            match keyword {
+                "Ka" => material.ambient = parse_color()?,
+                "Kd" => material.diffuse = parse_color()?,
+                "Ks" => material.specular = parse_color()?,
+                "Ns" => material.shininess = parse_float(0)?,
+                "d" => material.dissolve = parse_float(chunks.len().max(1) - 1)?,
+                "Tr" => material.dissolve = 1.0 - parse_float(0)?,
+                "illum" => {
+                    let chunk = match chunks.first() {
+                        Some(&v) => v,
+                        None => return Err(parse_error(filename, line_number, line.trim())),
+                    };
+
+                    material.illum = chunk
+                        .parse()
+                        .map_err(|_| parse_error(filename, line_number, chunk))?;
+                }
+                "map_Kd" => {
+                    // Options such as `-s 1 1 1` come before the file name.
+                    match chunks.last() {
+                        Some(&v) => material.diffuse_map = Some(v.to_string()),
+                        None => return Err(parse_error(filename, line_number, line.trim())),
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        Ok(materials)
+    }
+
+    /// Writes the given materials as an mtl library.
+    pub fn write_file(materials: &[Material], filename: &str) -> Result<(), RangleError> {
+        let mut writer = BufWriter::new(File::create(filename)?);
+
+        writeln!(writer, "# Written by rangle")?;
+        for material in materials {
+            writeln!(writer)?;
+            writeln!(writer, "newmtl {}", material.name)?;
+
+            let (ka, kd, ks) = (material.ambient, material.diffuse, material.specular);
+            writeln!(writer, "Ka {} {} {}", ka.x, ka.y, ka.z)?;
+            writeln!(writer, "Kd {} {} {}", kd.x, kd.y, kd.z)?;
+            writeln!(writer, "Ks {} {} {}", ks.x, ks.y, ks.z)?;
+            writeln!(writer, "Ns {}", material.shininess)?;
+            writeln!(writer, "d {}", material.dissolve)?;
+            writeln!(writer, "illum {}", material.illum)?;
+            if let Some(map) = &material.diffuse_map {
+                writeln!(writer, "map_Kd {}", map)?;
+            }
+        }
+
+        writer.flush()?;
+
+        Ok(())
+    }
+
+    /// Returns `Kd` with the dissolve value as its alpha.
+    pub fn get_diffuse_color(&self) -> Vec4 {
+        Vec4::from((self.diffuse, self.dissolve))
+    }
+}
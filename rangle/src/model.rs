@@ -1,112 +1,685 @@
 use std::{
-    fs::File,
-    io::{BufRead, BufReader},
+    collections::HashMap,
+    fs::{self, File},
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    ops::Range,
+    path::Path,
 };
 
-use glam::{Mat4, Quat, Vec3, EulerRot};
+use glam::{EulerRot, Mat4, Quat, Vec2, Vec3, Vec4};
 
-use crate::error::RangleError;
+use crate::{error::RangleError, material::Material};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SubMeshKind {
+    /// Started by an `o` statement
+    Object,
+    /// Started by a `g` statement
+    Group,
+}
+
+/// A named part of a model.
+/// Objects and groups may overlap, so a triangle can belong to both an object and a group.
+#[derive(Clone, Debug)]
+pub struct SubMesh {
+    pub name: String,
+    pub kind: SubMeshKind,
+    /// The range of the model's index buffer which makes up this sub-mesh
+    pub indices: Range<usize>,
+}
 
 #[derive(Clone)]
 pub struct Model {
     pub(crate) vertices: Vec<f32>,
     pub(crate) vertex_indices: Vec<usize>,
+    pub(crate) tex_coords: Vec<f32>,
+    pub(crate) tex_coord_indices: Vec<Option<usize>>,
+    pub(crate) normals: Vec<f32>,
+    pub(crate) normal_indices: Vec<Option<usize>>,
+    /// RGBA colors in `[0, 1]`, one per vertex, or empty if the model has none
+    pub(crate) colors: Vec<f32>,
+    pub(crate) materials: Vec<Material>,
+    pub(crate) face_materials: Vec<Option<usize>>,
+    pub(crate) sub_meshes: Vec<SubMesh>,
     center: (f32, f32, f32),
     scale: (f32, f32, f32),
     rotate: (f32, f32, f32),
     translate: (f32, f32, f32),
 }
 
+fn compute_center(vertices: &[f32]) -> (f32, f32, f32) {
+    let mut sum_x = 0.0;
+    let mut sum_y = 0.0;
+    let mut sum_z = 0.0;
+    let v_len = (vertices.len() / 3) as f32;
+    for i in (0..vertices.len()).step_by(3) {
+        sum_x += vertices[i];
+        sum_y += vertices[i + 1];
+        sum_z += vertices[i + 2];
+    }
+
+    (sum_x / v_len, sum_y / v_len, sum_z / v_len)
+}
+
+fn close_sub_mesh(
+    sub_meshes: &mut Vec<SubMesh>,
+    name: String,
+    kind: SubMeshKind,
+    start: usize,
+    end: usize,
+) {
+    if start < end {
+        sub_meshes.push(SubMesh {
+            name,
+            kind,
+            indices: start..end,
+        });
+    }
+}
+
+fn stl_error(filename: &str, reason: String) -> RangleError {
+    RangleError::StlParse {
+        filename: filename.to_string(),
+        reason,
+    }
+}
+
+/// Collects the triangles of an STL file, merging vertices with identical positions.
+struct StlBuilder {
+    vertices: Vec<f32>,
+    vertex_indices: Vec<usize>,
+    normals: Vec<f32>,
+    normal_indices: Vec<Option<usize>>,
+    vertex_map: HashMap<[u32; 3], usize>,
+}
+
+impl StlBuilder {
+    fn new() -> Self {
+        StlBuilder {
+            vertices: vec![],
+            vertex_indices: vec![],
+            normals: vec![],
+            normal_indices: vec![],
+            vertex_map: HashMap::new(),
+        }
+    }
+
+    fn add_vertex(&mut self, v: Vec3) -> usize {
+        // `+ 0.0` turns -0.0 into 0.0 so both share a key.
+        let key = [
+            (v.x + 0.0).to_bits(),
+            (v.y + 0.0).to_bits(),
+            (v.z + 0.0).to_bits(),
+        ];
+
+        let vertices = &mut self.vertices;
+        *self.vertex_map.entry(key).or_insert_with(|| {
+            vertices.extend_from_slice(&[v.x, v.y, v.z]);
+            vertices.len() / 3 - 1
+        })
+    }
+
+    fn add_facet(&mut self, normal: Vec3, corners: &[Vec3]) {
+        let normal_index = if normal == Vec3::ZERO {
+            None
+        } else {
+            self.normals
+                .extend_from_slice(&[normal.x, normal.y, normal.z]);
+            Some(self.normals.len() / 3 - 1)
+        };
+
+        for i in 1..corners.len() - 1 {
+            for &corner in &[corners[0], corners[i], corners[i + 1]] {
+                let index = self.add_vertex(corner);
+                self.vertex_indices.push(index);
+                self.normal_indices.push(normal_index);
+            }
+        }
+    }
+
+    fn build(self) -> Model {
+        let mut model = Model::from_vectors(self.vertices, self.vertex_indices);
+        model.normals = self.normals;
+        model.normal_indices = self.normal_indices;
+
+        model
+    }
+}
+
+fn parse_error(filename: &str, line: usize, token: &str) -> RangleError {
+    RangleError::ObjParse {
+        filename: filename.to_string(),
+        line,
+        token: token.to_string(),
+    }
+}
+
+/// Resolves a one-based (or negative, relative) OBJ index into a zero-based index
+/// into a list which currently holds `count` elements.
+fn resolve_index(index: &str, count: usize) -> Option<usize> {
+    let index: isize = index.parse().ok()?;
+
+    let resolved = if index > 0 {
+        index - 1
+    } else if index < 0 {
+        count as isize + index
+    } else {
+        return None;
+    };
+
+    if resolved < 0 || resolved as usize >= count {
+        return None;
+    }
+
+    Some(resolved as usize)
+}
+
 impl Model {
-    pub fn from_file<'a>(filename: &'a str) -> Result<Self, RangleError> {
+    pub fn from_file(filename: &str) -> Result<Self, RangleError> {
         let file = File::open(filename)?;
 
         let mut vertices = vec![];
         let mut vertex_indices = vec![];
+        let mut tex_coords = vec![];
+        let mut tex_coord_indices = vec![];
+        let mut normals = vec![];
+        let mut normal_indices = vec![];
+        let mut materials: Vec<Material> = vec![];
+        let mut face_materials = vec![];
+        let mut current_material = None;
+        let mut sub_meshes = vec![];
+        let mut current_object: Option<(String, usize)> = None;
+        let mut current_groups: Vec<(String, usize)> = vec![];
 
         let reader = BufReader::new(file);
 
-        let mut vx: f32;
-        let mut vy: f32;
-        let mut vz: f32;
-        let mut index1: usize;
-        let mut index2: usize;
-        let mut index3: usize;
-        for line in reader.lines() {
+        for (line_number, line) in reader.lines().enumerate() {
             let line = line?;
+            let line_number = line_number + 1;
+
+            let mut chunks = line.split_whitespace();
+            let keyword = match chunks.next() {
+                Some(v) => v,
+                None => continue,
+            };
+            let chunks: Vec<&str> = chunks.collect();
+
+            let parse_floats = |count: usize, required: usize| -> Result<Vec<f32>, RangleError> {
+                if chunks.len() < required {
+                    return Err(parse_error(filename, line_number, line.trim()));
+                }
+
+                let mut rv = vec![0.0; count];
+                for (i, &chunk) in chunks.iter().take(count).enumerate() {
+                    rv[i] = chunk
+                        .parse()
+                        .map_err(|_| parse_error(filename, line_number, chunk))?;
+                }
+
+                Ok(rv)
+            };
+
+            match keyword {
+                "v" => vertices.extend(parse_floats(3, 3)?),
+                "vt" => tex_coords.extend(parse_floats(2, 1)?),
+                "vn" => normals.extend(parse_floats(3, 3)?),
+                "f" => {
+                    if chunks.len() < 3 {
+                        return Err(parse_error(filename, line_number, line.trim()));
+                    }
+
+                    let mut corners = vec![];
+                    for &chunk in &chunks {
+                        let mut parts = chunk.split('/');
 
-            let chunks: Vec<&str>;
-
-            if line.starts_with("v ") {
-                chunks = line
-                    .strip_prefix("v ")
-                    .unwrap()
-                    .split(' ')
-                    .filter(|&s| !s.is_empty())
-                    .collect();
-
-                // TODO: Stop unwrapping and return a real error
-                vx = chunks[0].parse().unwrap();
-                vy = chunks[1].parse().unwrap();
-                vz = chunks[2].parse().unwrap();
-
-                vertices.push(vx);
-                vertices.push(vy);
-                vertices.push(vz);
-            } else if line.starts_with("f ") {
-                chunks = line
-                    .strip_prefix("f ")
-                    .unwrap()
-                    .split(' ')
-                    .filter(|&s| !s.is_empty())
-                    .collect();
-                for i in 1..=chunks.len() - 2 {
-                    index1 = chunks[0].split('/').nth(0).unwrap().parse().unwrap();
-                    index2 = chunks[i].split('/').nth(0).unwrap().parse().unwrap();
-                    index3 = chunks[i + 1].split('/').nth(0).unwrap().parse().unwrap();
-
-                    vertex_indices.push(index1 - 1);
-                    vertex_indices.push(index2 - 1);
-                    vertex_indices.push(index3 - 1);
+                        let v = parts
+                            .next()
+                            .and_then(|v| resolve_index(v, vertices.len() / 3))
+                            .ok_or_else(|| parse_error(filename, line_number, chunk))?;
+
+                        let vt = match parts.next() {
+                            Some(vt) if !vt.is_empty() => Some(
+                                resolve_index(vt, tex_coords.len() / 2)
+                                    .ok_or_else(|| parse_error(filename, line_number, chunk))?,
+                            ),
+                            _ => None,
+                        };
+
+                        let vn = match parts.next() {
+                            Some(vn) if !vn.is_empty() => Some(
+                                resolve_index(vn, normals.len() / 3)
+                                    .ok_or_else(|| parse_error(filename, line_number, chunk))?,
+                            ),
+                            _ => None,
+                        };
+
+                        if parts.next().is_some() {
+                            return Err(parse_error(filename, line_number, chunk));
+                        }
+
+                        corners.push((v, vt, vn));
+                    }
+
+                    for i in 1..corners.len() - 1 {
+                        for &(v, vt, vn) in &[corners[0], corners[i], corners[i + 1]] {
+                            vertex_indices.push(v);
+                            tex_coord_indices.push(vt);
+                            normal_indices.push(vn);
+                        }
+
+                        face_materials.push(current_material);
+                    }
+                }
+                "mtllib" => {
+                    let directory = Path::new(filename)
+                        .parent()
+                        .unwrap_or_else(|| Path::new(""));
+
+                    // Exported models are often shared without their material libraries,
+                    // so a missing library only leaves its faces without a material.
+                    let library = directory.join(line.trim()[keyword.len()..].trim());
+                    let library = library.to_string_lossy();
+                    match Material::from_file(&library) {
+                        Ok(v) => materials.extend(v),
+                        Err(RangleError::Io(e)) if e.kind() == io::ErrorKind::NotFound => (),
+                        Err(e) => return Err(e),
+                    }
                 }
+                "o" => {
+                    // Groups are scoped to the object they were started in.
+                    for (name, start) in current_groups.drain(..) {
+                        close_sub_mesh(
+                            &mut sub_meshes,
+                            name,
+                            SubMeshKind::Group,
+                            start,
+                            vertex_indices.len(),
+                        );
+                    }
+                    if let Some((name, start)) = current_object.take() {
+                        close_sub_mesh(
+                            &mut sub_meshes,
+                            name,
+                            SubMeshKind::Object,
+                            start,
+                            vertex_indices.len(),
+                        );
+                    }
+
+                    let name = line.trim()[keyword.len()..].trim().to_string();
+                    current_object = Some((name, vertex_indices.len()));
+                }
+                "g" => {
+                    for (name, start) in current_groups.drain(..) {
+                        close_sub_mesh(
+                            &mut sub_meshes,
+                            name,
+                            SubMeshKind::Group,
+                            start,
+                            vertex_indices.len(),
+                        );
+                    }
+
+                    // A group statement may place the following faces in several groups at once.
+                    for &name in &chunks {
+                        current_groups.push((name.to_string(), vertex_indices.len()));
+                    }
+                }
+                "usemtl" => {
+                    let name = line.trim()[keyword.len()..].trim();
+                    current_material = materials.iter().position(|m| m.name == name);
+                }
+                _ => (),
             }
         }
 
-        let mut sum_x = 0.0;
-        let mut sum_y = 0.0;
-        let mut sum_z = 0.0;
-        let v_len = (vertices.len() / 3) as f32;
-        for i in (0..vertices.len()).step_by(3) {
-            sum_x += vertices[i];
-            sum_y += vertices[i + 1];
-            sum_z += vertices[i + 2];
+        if let Some((name, start)) = current_object {
+            close_sub_mesh(
+                &mut sub_meshes,
+                name,
+                SubMeshKind::Object,
+                start,
+                vertex_indices.len(),
+            );
+        }
+        for (name, start) in current_groups {
+            close_sub_mesh(
+                &mut sub_meshes,
+                name,
+                SubMeshKind::Group,
+                start,
+                vertex_indices.len(),
+            );
         }
+        sub_meshes.sort_by_key(|m| m.indices.start);
 
         Ok(Model {
-            vertices: vertices,
-            vertex_indices: vertex_indices,
-            center: (sum_x / v_len, sum_y / v_len, sum_z / v_len),
+            center: compute_center(&vertices),
+            vertices,
+            vertex_indices,
+            tex_coords,
+            tex_coord_indices,
+            normals,
+            normal_indices,
+            colors: vec![],
+            materials,
+            face_materials,
+            sub_meshes,
             scale: (1.0, 1.0, 1.0),
             translate: (0.0, 0.0, 0.0),
             rotate: (0.0, 0.0, 0.0),
         })
     }
 
-    pub fn from_vectors(vertices: Vec<f32>, vertex_indices: Vec<usize>) -> Self {
-        let mut sum_x = 0.0;
-        let mut sum_y = 0.0;
-        let mut sum_z = 0.0;
-        let v_len = (vertices.len() / 3) as f32;
-        for i in (0..vertices.len()).step_by(3) {
-            sum_x += vertices[i];
-            sum_y += vertices[i + 1];
-            sum_z += vertices[i + 2];
+    /// Reads an ASCII or binary STL file.
+    pub fn from_stl(filename: &str) -> Result<Self, RangleError> {
+        let bytes = fs::read(filename)?;
+
+        // Binary files may also begin with "solid", so the size is checked first.
+        let is_binary = bytes.len() >= 84 && {
+            let count = u32::from_le_bytes([bytes[80], bytes[81], bytes[82], bytes[83]]);
+            bytes.len() as u64 == 84 + 50 * count as u64
+        };
+
+        let text_start = bytes
+            .iter()
+            .position(|b| !b.is_ascii_whitespace())
+            .unwrap_or(bytes.len());
+
+        if is_binary || !bytes[text_start..].starts_with(b"solid") {
+            Self::from_binary_stl(filename, &bytes)
+        } else {
+            Self::from_ascii_stl(filename, &bytes)
+        }
+    }
+
+    fn from_binary_stl(filename: &str, bytes: &[u8]) -> Result<Self, RangleError> {
+        if bytes.len() < 84 {
+            return Err(stl_error(filename, "missing header".to_string()));
         }
 
+        let count = u32::from_le_bytes([bytes[80], bytes[81], bytes[82], bytes[83]]) as usize;
+        if bytes.len() < 84 + 50 * count {
+            return Err(stl_error(
+                filename,
+                format!("expected {} triangles but the file ends early", count),
+            ));
+        }
+
+        let read_vec3 = |offset: usize| {
+            let mut v = [0.0; 3];
+            for (i, c) in v.iter_mut().enumerate() {
+                let o = offset + 4 * i;
+                *c = f32::from_le_bytes([bytes[o], bytes[o + 1], bytes[o + 2], bytes[o + 3]]);
+            }
+
+            Vec3::from(v)
+        };
+
+        let mut builder = StlBuilder::new();
+        let mut offset;
+        for i in 0..count {
+            offset = 84 + 50 * i;
+
+            builder.add_facet(
+                read_vec3(offset),
+                &[
+                    read_vec3(offset + 12),
+                    read_vec3(offset + 24),
+                    read_vec3(offset + 36),
+                ],
+            );
+        }
+
+        Ok(builder.build())
+    }
+
+    fn from_ascii_stl(filename: &str, bytes: &[u8]) -> Result<Self, RangleError> {
+        let text = String::from_utf8_lossy(bytes);
+
+        let mut builder = StlBuilder::new();
+        let mut normal = Vec3::ZERO;
+        let mut corners = vec![];
+        let mut in_loop = false;
+
+        for (line_number, line) in text.lines().enumerate() {
+            let line_number = line_number + 1;
+
+            let mut chunks = line.split_whitespace();
+            let keyword = match chunks.next() {
+                Some(v) => v,
+                None => continue,
+            };
+            let chunks: Vec<&str> = chunks.collect();
+
+            let invalid = |token: &str| {
+                stl_error(
+                    filename,
+                    format!("line {}: invalid token `{}`", line_number, token),
+                )
+            };
+
+            let parse_vec3 = |chunks: &[&str]| -> Result<Vec3, RangleError> {
+                if chunks.len() != 3 {
+                    return Err(invalid(line.trim()));
+                }
+
+                let mut v = [0.0; 3];
+                for (i, &chunk) in chunks.iter().enumerate() {
+                    v[i] = chunk.parse().map_err(|_| invalid(chunk))?;
+                }
+
+                Ok(Vec3::from(v))
+            };
+
+            match keyword {
+                "solid" | "endsolid" | "endfacet" => (),
+                "facet" => {
+                    if chunks.first() != Some(&"normal") {
+                        return Err(invalid(line.trim()));
+                    }
+
+                    normal = parse_vec3(&chunks[1..])?;
+                }
+                "outer" => {
+                    if chunks != ["loop"] || in_loop {
+                        return Err(invalid(line.trim()));
+                    }
+
+                    in_loop = true;
+                }
+                "vertex" => {
+                    if !in_loop {
+                        return Err(invalid(keyword));
+                    }
+
+                    corners.push(parse_vec3(&chunks)?);
+                }
+                "endloop" => {
+                    if !in_loop || corners.len() < 3 {
+                        return Err(invalid(keyword));
+                    }
+
+                    builder.add_facet(normal, &corners);
+                    corners.clear();
+                    normal = Vec3::ZERO;
+                    in_loop = false;
+                }
+                _ => return Err(invalid(keyword)),
+            }
+        }
+
+        if in_loop {
+            return Err(stl_error(filename, "unterminated facet".to_string()));
+        }
+
+        Ok(builder.build())
+    }
+
+    /// Returns the vertex positions, transformed by the model matrix if `bake_transform` is set.
+    pub(crate) fn get_export_vertices(&self, bake_transform: bool) -> Vec<Vec3> {
+        let matrix = self.compute_model_matrix();
+
+        self.vertices
+            .chunks(3)
+            .map(|v| {
+                let v = Vec3::new(v[0], v[1], v[2]);
+                if bake_transform {
+                    matrix.transform_point3(v)
+                } else {
+                    v
+                }
+            })
+            .collect()
+    }
+
+    /// Returns the normals, transformed by the model matrix if `bake_transform` is set.
+    fn get_export_normals(&self, bake_transform: bool) -> Vec<Vec3> {
+        let matrix = self.compute_model_matrix().inverse().transpose();
+
+        self.normals
+            .chunks(3)
+            .map(|n| {
+                let n = Vec3::new(n[0], n[1], n[2]);
+                if bake_transform {
+                    matrix.transform_vector3(n).normalize_or_zero()
+                } else {
+                    n
+                }
+            })
+            .collect()
+    }
+
+    /// Writes the model as a Wavefront OBJ file.
+    /// Materials are written to an mtl library next to it with the same file stem.
+    /// Faces without a material which follow faces with one are given a default material,
+    /// since OBJ cannot switch back to no material.
+    pub fn write_obj(&self, filename: &str, bake_transform: bool) -> Result<(), RangleError> {
+        let mut writer = BufWriter::new(File::create(filename)?);
+
+        writeln!(writer, "# Written by rangle")?;
+
+        let mut materials = self.materials.clone();
+        let needs_default = self
+            .face_materials
+            .iter()
+            .skip_while(|m| m.is_none())
+            .any(Option::is_none);
+        if needs_default {
+            let mut name = "default".to_string();
+            while materials.iter().any(|m| m.name == name) {
+                name.push('_');
+            }
+            materials.push(Material::new(&name));
+        }
+
+        if !materials.is_empty() {
+            let library = Path::new(filename).with_extension("mtl");
+            Material::write_file(&materials, &library.to_string_lossy())?;
+
+            let library_name = library.file_name().unwrap_or_default().to_string_lossy();
+            writeln!(writer, "mtllib {}", library_name)?;
+        }
+
+        for v in self.get_export_vertices(bake_transform) {
+            writeln!(writer, "v {} {} {}", v.x, v.y, v.z)?;
+        }
+        for vt in self.tex_coords.chunks(2) {
+            writeln!(writer, "vt {} {}", vt[0], vt[1])?;
+        }
+        for vn in self.get_export_normals(bake_transform) {
+            writeln!(writer, "vn {} {} {}", vn.x, vn.y, vn.z)?;
+        }
+
+        let mut current_material = None;
+        for i in (0..self.vertex_indices.len()).step_by(3) {
+            for sub_mesh in self.sub_meshes.iter().filter(|m| m.indices.start == i) {
+                match sub_mesh.kind {
+                    SubMeshKind::Object => writeln!(writer, "o {}", sub_mesh.name)?,
+                    SubMeshKind::Group => writeln!(writer, "g {}", sub_mesh.name)?,
+                }
+            }
+
+            // The default material is the last one, and only needed once another was used.
+            let material = match self.face_materials[i / 3] {
+                None if current_material.is_some() => Some(materials.len() - 1),
+                material => material,
+            };
+            if material != current_material {
+                if let Some(index) = material {
+                    writeln!(writer, "usemtl {}", materials[index].name)?;
+                }
+                current_material = material;
+            }
+
+            write!(writer, "f")?;
+            for j in i..i + 3 {
+                write!(writer, " {}", self.vertex_indices[j] + 1)?;
+                match (self.tex_coord_indices[j], self.normal_indices[j]) {
+                    (Some(vt), Some(vn)) => write!(writer, "/{}/{}", vt + 1, vn + 1)?,
+                    (Some(vt), None) => write!(writer, "/{}", vt + 1)?,
+                    (None, Some(vn)) => write!(writer, "//{}", vn + 1)?,
+                    (None, None) => (),
+                }
+            }
+            writeln!(writer)?;
+        }
+
+        writer.flush()?;
+
+        Ok(())
+    }
+
+    /// Writes the model as a binary STL file.
+    /// Facet normals are computed from the (possibly transformed) vertex positions.
+    pub fn write_stl(&self, filename: &str, bake_transform: bool) -> Result<(), RangleError> {
+        let mut writer = BufWriter::new(File::create(filename)?);
+
+        let vertices = self.get_export_vertices(bake_transform);
+
+        let mut header = [0u8; 80];
+        header[..b"binary stl written by rangle".len()]
+            .copy_from_slice(b"binary stl written by rangle");
+        writer.write_all(&header)?;
+        writer.write_all(&((self.vertex_indices.len() / 3) as u32).to_le_bytes())?;
+
+        let mut v0;
+        let mut v1;
+        let mut v2;
+        for i in (0..self.vertex_indices.len()).step_by(3) {
+            v0 = vertices[self.vertex_indices[i]];
+            v1 = vertices[self.vertex_indices[i + 1]];
+            v2 = vertices[self.vertex_indices[i + 2]];
+
+            let normal = (v1 - v0).cross(v2 - v0).normalize_or_zero();
+            for v in &[normal, v0, v1, v2] {
+                for c in &[v.x, v.y, v.z] {
+                    writer.write_all(&c.to_le_bytes())?;
+                }
+            }
+            writer.write_all(&[0, 0])?;
+        }
+
+        writer.flush()?;
+
+        Ok(())
+    }
+
+    pub fn from_vectors(vertices: Vec<f32>, vertex_indices: Vec<usize>) -> Self {
+        let index_count = vertex_indices.len();
+
         Model {
-            vertices: vertices,
-            vertex_indices: vertex_indices,
-            center: (sum_x / v_len, sum_y / v_len, sum_z / v_len),
+            center: compute_center(&vertices),
+            vertices,
+            vertex_indices,
+            tex_coords: vec![],
+            tex_coord_indices: vec![None; index_count],
+            normals: vec![],
+            normal_indices: vec![None; index_count],
+            colors: vec![],
+            materials: vec![],
+            face_materials: vec![None; index_count / 3],
+            sub_meshes: vec![],
             scale: (1.0, 1.0, 1.0),
             translate: (0.0, 0.0, 0.0),
             rotate: (0.0, 0.0, 0.0),
@@ -134,6 +707,294 @@ impl Model {
         rv
     }
 
+    pub fn has_tex_coords(&self) -> bool {
+        self.tex_coord_indices.iter().any(Option::is_some)
+    }
+
+    pub fn has_normals(&self) -> bool {
+        self.normal_indices.iter().any(Option::is_some)
+    }
+
+    /// Returns one texture coordinate per entry in the index buffer.
+    /// Corners without a texture coordinate are given `(0, 0)`.
+    pub fn get_tex_coord_buffer(&self) -> Vec<Vec2> {
+        let mut rv = vec![];
+
+        let mut i0;
+        for index in &self.tex_coord_indices {
+            rv.push(match index {
+                Some(index) => {
+                    i0 = 2 * index;
+                    Vec2::new(self.tex_coords[i0], self.tex_coords[i0 + 1])
+                }
+                None => Vec2::ZERO,
+            });
+        }
+
+        rv
+    }
+
+    /// Returns one normal per entry in the index buffer.
+    /// Corners without a normal are given `(0, 0, 0)`.
+    pub fn get_normal_buffer(&self) -> Vec<Vec3> {
+        let mut rv = vec![];
+
+        let mut i0;
+        for index in &self.normal_indices {
+            rv.push(match index {
+                Some(index) => {
+                    i0 = 3 * index;
+                    Vec3::new(self.normals[i0], self.normals[i0 + 1], self.normals[i0 + 2])
+                }
+                None => Vec3::ZERO,
+            });
+        }
+
+        rv
+    }
+
+    /// Returns one flat normal per entry in the index buffer,
+    /// each being the normal of the triangle it belongs to.
+    pub fn get_face_normal_buffer(&self) -> Vec<Vec3> {
+        let mut rv = vec![];
+
+        let positions = self.get_vertex_buffer();
+        let mut normal;
+        for i in (0..positions.len()).step_by(3) {
+            normal = (positions[i + 1] - positions[i])
+                .cross(positions[i + 2] - positions[i + 1])
+                .normalize_or_zero();
+
+            rv.push(normal);
+            rv.push(normal);
+            rv.push(normal);
+        }
+
+        rv
+    }
+
+    /// Replaces the model's normals with smooth vertex normals.
+    ///
+    /// Each corner's normal is the average of the normals of the triangles sharing its position,
+    /// weighted by their area and the angle they make at that corner.
+    /// Triangles whose normals differ from the corner's triangle by more than `crease_angle`
+    /// (in radians) are left out, so vertices are split across sharp edges.
+    pub fn compute_vertex_normals(&mut self, crease_angle: f32) {
+        let positions = self.get_vertex_buffer();
+        let triangle_count = positions.len() / 3;
+
+        let mut face_normals = Vec::with_capacity(triangle_count);
+        let mut corner_weights = Vec::with_capacity(positions.len());
+        for i in (0..positions.len()).step_by(3) {
+            let (p0, p1, p2) = (positions[i], positions[i + 1], positions[i + 2]);
+
+            // The length of the cross product is twice the triangle's area.
+            let cross = (p1 - p0).cross(p2 - p0);
+            face_normals.push(cross.normalize_or_zero());
+
+            let area = cross.length();
+            corner_weights.push(area * (p1 - p0).angle_between(p2 - p0));
+            corner_weights.push(area * (p2 - p1).angle_between(p0 - p1));
+            corner_weights.push(area * (p0 - p2).angle_between(p1 - p2));
+        }
+
+        // Corners are grouped by position rather than index so that seams in the
+        // texture coordinates do not split the normals.
+        let keys: Vec<[u32; 3]> = positions
+            .iter()
+            .map(|p| {
+                [
+                    (p.x + 0.0).to_bits(),
+                    (p.y + 0.0).to_bits(),
+                    (p.z + 0.0).to_bits(),
+                ]
+            })
+            .collect();
+        let mut corners_at: HashMap<[u32; 3], Vec<usize>> = HashMap::new();
+        for (corner, &key) in keys.iter().enumerate() {
+            corners_at.entry(key).or_default().push(corner);
+        }
+
+        let min_cos = crease_angle.cos();
+
+        let mut normals = vec![];
+        let mut normal_indices = vec![None; positions.len()];
+        let mut normal_map: HashMap<(usize, [u32; 3]), usize> = HashMap::new();
+        for (corner, key) in keys.iter().enumerate() {
+            let face_normal = face_normals[corner / 3];
+
+            let mut sum = Vec3::ZERO;
+            for &other in &corners_at[key] {
+                // Degenerate triangles have no normal to add, and their angles can be NaN.
+                let weight = corner_weights[other];
+                if weight == 0.0 || !weight.is_finite() {
+                    continue;
+                }
+
+                let other_normal = face_normals[other / 3];
+                if face_normal == Vec3::ZERO || face_normal.dot(other_normal) >= min_cos {
+                    sum += other_normal * weight;
+                }
+            }
+
+            let normal = sum.normalize_or_zero();
+
+            let normal_key = (
+                self.vertex_indices[corner],
+                [normal.x.to_bits(), normal.y.to_bits(), normal.z.to_bits()],
+            );
+            let index = *normal_map.entry(normal_key).or_insert_with(|| {
+                normals.extend_from_slice(&[normal.x, normal.y, normal.z]);
+                normals.len() / 3 - 1
+            });
+
+            normal_indices[corner] = Some(index);
+        }
+
+        self.normals = normals;
+        self.normal_indices = normal_indices;
+    }
+
+    pub fn has_vertex_colors(&self) -> bool {
+        !self.colors.is_empty()
+    }
+
+    /// Returns one vertex color per entry in the index buffer.
+    /// If the model has no vertex colors, every entry is `default`.
+    pub fn get_color_buffer(&self, default: Vec4) -> Vec<Vec4> {
+        if !self.has_vertex_colors() {
+            return vec![default; self.vertex_indices.len()];
+        }
+
+        let mut rv = vec![];
+
+        let mut i0;
+        for i in 0..self.vertex_indices.len() {
+            i0 = 4 * self.vertex_indices[i];
+
+            rv.push(Vec4::new(
+                self.colors[i0],
+                self.colors[i0 + 1],
+                self.colors[i0 + 2],
+                self.colors[i0 + 3],
+            ));
+        }
+
+        rv
+    }
+
+    pub fn has_materials(&self) -> bool {
+        self.face_materials.iter().any(Option::is_some)
+    }
+
+    pub fn get_materials(&self) -> &[Material] {
+        &self.materials
+    }
+
+    /// Returns the index into `get_materials` of each triangle's material, if it has one.
+    pub fn get_face_materials(&self) -> &[Option<usize>] {
+        &self.face_materials
+    }
+
+    /// Returns one diffuse color per entry in the index buffer, taken from the
+    /// material of the triangle it belongs to.
+    /// Triangles without a material are given `default`.
+    pub fn get_material_color_buffer(&self, default: Vec4) -> Vec<Vec4> {
+        let mut rv = vec![];
+
+        let mut color;
+        for material in &self.face_materials {
+            color = match material {
+                Some(index) => self.materials[*index].get_diffuse_color(),
+                None => default,
+            };
+
+            rv.push(color);
+            rv.push(color);
+            rv.push(color);
+        }
+
+        rv
+    }
+
+    pub fn get_sub_meshes(&self) -> &[SubMesh] {
+        &self.sub_meshes
+    }
+
+    /// Keeps only the triangles belonging to a sub-mesh with one of the given names.
+    pub fn retain_sub_meshes(&mut self, names: &[&str]) -> Result<(), RangleError> {
+        let keep = self.mark_sub_meshes(names)?;
+
+        self.retain_triangles(&keep);
+
+        Ok(())
+    }
+
+    /// Removes the triangles belonging to a sub-mesh with any of the given names.
+    pub fn remove_sub_meshes(&mut self, names: &[&str]) -> Result<(), RangleError> {
+        let keep: Vec<bool> = self.mark_sub_meshes(names)?.iter().map(|&v| !v).collect();
+
+        self.retain_triangles(&keep);
+
+        Ok(())
+    }
+
+    /// Marks each triangle which belongs to a sub-mesh with one of the given names.
+    fn mark_sub_meshes(&self, names: &[&str]) -> Result<Vec<bool>, RangleError> {
+        let mut marked = vec![false; self.vertex_indices.len() / 3];
+
+        for &name in names {
+            let mut found = false;
+            for sub_mesh in self.sub_meshes.iter().filter(|m| m.name == name) {
+                found = true;
+                for i in sub_mesh.indices.clone().step_by(3) {
+                    marked[i / 3] = true;
+                }
+            }
+
+            if !found {
+                return Err(RangleError::UnknownSubMesh(name.to_string()));
+            }
+        }
+
+        Ok(marked)
+    }
+
+    fn retain_triangles(&mut self, keep: &[bool]) {
+        // The number of kept indices before each triangle, used to move the sub-mesh ranges.
+        let mut new_offsets = Vec::with_capacity(keep.len() + 1);
+        let mut offset = 0;
+        for &k in keep {
+            new_offsets.push(offset);
+            if k {
+                offset += 3;
+            }
+        }
+        new_offsets.push(offset);
+
+        let mut vertex_indices = Vec::with_capacity(offset);
+        let mut tex_coord_indices = Vec::with_capacity(offset);
+        let mut normal_indices = Vec::with_capacity(offset);
+        let mut face_materials = Vec::with_capacity(offset / 3);
+        for (i, _) in keep.iter().enumerate().filter(|(_, &k)| k) {
+            vertex_indices.extend_from_slice(&self.vertex_indices[3 * i..3 * i + 3]);
+            tex_coord_indices.extend_from_slice(&self.tex_coord_indices[3 * i..3 * i + 3]);
+            normal_indices.extend_from_slice(&self.normal_indices[3 * i..3 * i + 3]);
+            face_materials.push(self.face_materials[i]);
+        }
+
+        self.vertex_indices = vertex_indices;
+        self.tex_coord_indices = tex_coord_indices;
+        self.normal_indices = normal_indices;
+        self.face_materials = face_materials;
+
+        for sub_mesh in &mut self.sub_meshes {
+            sub_mesh.indices =
+                new_offsets[sub_mesh.indices.start / 3]..new_offsets[sub_mesh.indices.end / 3];
+        }
+        self.sub_meshes.retain(|m| !m.indices.is_empty());
+    }
+
     pub fn scale(&mut self, x: f32, y: f32, z: f32) {
         self.scale = (x, y, z);
     }
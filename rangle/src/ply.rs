@@ -0,0 +1,376 @@
+use std::{
+    fs::{self, File},
+    io::{BufWriter, Write},
+    str::SplitAsciiWhitespace,
+};
+
+use crate::{error::RangleError, model::Model};
+
+#[derive(Clone, Copy, PartialEq)]
+enum PlyFormat {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+#[derive(Clone, Copy)]
+enum PlyScalar {
+    Int8,
+    UInt8,
+    Int16,
+    UInt16,
+    Int32,
+    UInt32,
+    Float32,
+    Float64,
+}
+
+impl PlyScalar {
+    fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "char" | "int8" => PlyScalar::Int8,
+            "uchar" | "uint8" => PlyScalar::UInt8,
+            "short" | "int16" => PlyScalar::Int16,
+            "ushort" | "uint16" => PlyScalar::UInt16,
+            "int" | "int32" => PlyScalar::Int32,
+            "uint" | "uint32" => PlyScalar::UInt32,
+            "float" | "float32" => PlyScalar::Float32,
+            "double" | "float64" => PlyScalar::Float64,
+            _ => return None,
+        })
+    }
+
+    fn size(self) -> usize {
+        match self {
+            PlyScalar::Int8 | PlyScalar::UInt8 => 1,
+            PlyScalar::Int16 | PlyScalar::UInt16 => 2,
+            PlyScalar::Int32 | PlyScalar::UInt32 | PlyScalar::Float32 => 4,
+            PlyScalar::Float64 => 8,
+        }
+    }
+
+    /// The value which an integer color channel of this type reaches at full intensity.
+    fn color_max(self) -> f64 {
+        match self {
+            PlyScalar::Int8 => 127.0,
+            PlyScalar::UInt8 => 255.0,
+            PlyScalar::Int16 => 32767.0,
+            PlyScalar::UInt16 => 65535.0,
+            PlyScalar::Int32 => 2147483647.0,
+            PlyScalar::UInt32 => 4294967295.0,
+            PlyScalar::Float32 | PlyScalar::Float64 => 1.0,
+        }
+    }
+}
+
+enum PlyProperty {
+    Scalar {
+        name: String,
+        scalar: PlyScalar,
+    },
+    List {
+        name: String,
+        count: PlyScalar,
+        item: PlyScalar,
+    },
+}
+
+struct PlyElement {
+    name: String,
+    count: usize,
+    properties: Vec<PlyProperty>,
+}
+
+/// Reads the values of the body of a PLY file, regardless of its format.
+struct PlyReader<'a> {
+    format: PlyFormat,
+    bytes: &'a [u8],
+    position: usize,
+    tokens: Option<SplitAsciiWhitespace<'a>>,
+}
+
+impl<'a> PlyReader<'a> {
+    fn read(&mut self, scalar: PlyScalar) -> Result<f64, String> {
+        if let Some(tokens) = &mut self.tokens {
+            let token = tokens.next().ok_or("unexpected end of file")?;
+
+            return token
+                .parse()
+                .map_err(|_| format!("invalid value `{}`", token));
+        }
+
+        let size = scalar.size();
+        if self.position + size > self.bytes.len() {
+            return Err("unexpected end of file".to_string());
+        }
+
+        let mut raw = [0u8; 8];
+        raw[..size].copy_from_slice(&self.bytes[self.position..self.position + size]);
+        if self.format == PlyFormat::BinaryBigEndian {
+            raw[..size].reverse();
+        }
+        self.position += size;
+
+        Ok(match scalar {
+            PlyScalar::Int8 => raw[0] as i8 as f64,
+            PlyScalar::UInt8 => raw[0] as f64,
+            PlyScalar::Int16 => i16::from_le_bytes([raw[0], raw[1]]) as f64,
+            PlyScalar::UInt16 => u16::from_le_bytes([raw[0], raw[1]]) as f64,
+            PlyScalar::Int32 => i32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]]) as f64,
+            PlyScalar::UInt32 => u32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]]) as f64,
+            PlyScalar::Float32 => f32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]]) as f64,
+            PlyScalar::Float64 => f64::from_le_bytes(raw),
+        })
+    }
+
+    fn read_index(&mut self, scalar: PlyScalar) -> Result<usize, String> {
+        let value = self.read(scalar)?;
+        if value < 0.0 || value.fract() != 0.0 {
+            return Err(format!("invalid index `{}`", value));
+        }
+
+        Ok(value as usize)
+    }
+}
+
+fn parse_header(lines: &[&str]) -> Result<(PlyFormat, Vec<PlyElement>), String> {
+    if lines.first().map(|v| v.trim()) != Some("ply") {
+        return Err("missing `ply` magic number".to_string());
+    }
+
+    let mut format = None;
+    let mut elements: Vec<PlyElement> = vec![];
+
+    for line in &lines[1..] {
+        let chunks: Vec<&str> = line.split_whitespace().collect();
+        let invalid = || format!("invalid header line `{}`", line.trim());
+
+        match chunks.first() {
+            Some(&"format") => {
+                format = Some(match chunks.get(1) {
+                    Some(&"ascii") => PlyFormat::Ascii,
+                    Some(&"binary_little_endian") => PlyFormat::BinaryLittleEndian,
+                    Some(&"binary_big_endian") => PlyFormat::BinaryBigEndian,
+                    _ => return Err(invalid()),
+                });
+            }
+            Some(&"element") => {
+                if chunks.len() != 3 {
+                    return Err(invalid());
+                }
+
+                elements.push(PlyElement {
+                    name: chunks[1].to_string(),
+                    count: chunks[2].parse().map_err(|_| invalid())?,
+                    properties: vec![],
+                });
+            }
+            Some(&"property") => {
+                let element = elements.last_mut().ok_or_else(invalid)?;
+
+                let property = match chunks[1..] {
+                    ["list", count, item, name] => PlyProperty::List {
+                        name: name.to_string(),
+                        count: PlyScalar::from_name(count).ok_or_else(invalid)?,
+                        item: PlyScalar::from_name(item).ok_or_else(invalid)?,
+                    },
+                    [scalar, name] => PlyProperty::Scalar {
+                        name: name.to_string(),
+                        scalar: PlyScalar::from_name(scalar).ok_or_else(invalid)?,
+                    },
+                    _ => return Err(invalid()),
+                };
+
+                element.properties.push(property);
+            }
+            Some(&"comment") | Some(&"obj_info") | None => (),
+            _ => return Err(invalid()),
+        }
+    }
+
+    match format {
+        Some(v) => Ok((v, elements)),
+        None => Err("missing format".to_string()),
+    }
+}
+
+fn read_body(reader: &mut PlyReader, elements: &[PlyElement]) -> Result<Model, String> {
+    let mut vertices = vec![];
+    let mut colors = vec![];
+    let mut vertex_indices = vec![];
+
+    let has_colors = elements
+        .iter()
+        .filter(|e| e.name == "vertex")
+        .flat_map(|e| &e.properties)
+        .any(|p| match p {
+            PlyProperty::Scalar { name, .. } => {
+                matches!(name.as_str(), "red" | "green" | "blue" | "r" | "g" | "b")
+            }
+            _ => false,
+        });
+
+    for element in elements {
+        let is_vertex = element.name == "vertex";
+        let is_face = element.name == "face";
+
+        for _ in 0..element.count {
+            let mut position = [0.0; 3];
+            let mut color = [1.0; 4];
+            let mut polygon = vec![];
+
+            for property in &element.properties {
+                match property {
+                    PlyProperty::Scalar { name, scalar } => {
+                        let value = reader.read(*scalar)?;
+                        if !is_vertex {
+                            continue;
+                        }
+
+                        let channel = match name.as_str() {
+                            "x" => Some((&mut position[0], 1.0)),
+                            "y" => Some((&mut position[1], 1.0)),
+                            "z" => Some((&mut position[2], 1.0)),
+                            "red" | "r" => Some((&mut color[0], scalar.color_max())),
+                            "green" | "g" => Some((&mut color[1], scalar.color_max())),
+                            "blue" | "b" => Some((&mut color[2], scalar.color_max())),
+                            "alpha" | "a" => Some((&mut color[3], scalar.color_max())),
+                            _ => None,
+                        };
+
+                        if let Some((channel, max)) = channel {
+                            *channel = (value / max) as f32;
+                        }
+                    }
+                    PlyProperty::List { name, count, item } => {
+                        let count = reader.read_index(*count)?;
+
+                        let is_indices =
+                            is_face && (name == "vertex_indices" || name == "vertex_index");
+                        for _ in 0..count {
+                            if is_indices {
+                                polygon.push(reader.read_index(*item)?);
+                            } else {
+                                reader.read(*item)?;
+                            }
+                        }
+                    }
+                }
+            }
+
+            if is_vertex {
+                vertices.extend_from_slice(&position);
+                colors.extend_from_slice(&color);
+            } else if is_face {
+                if polygon.len() < 3 {
+                    return Err(format!("face with {} vertices", polygon.len()));
+                }
+
+                for i in 1..polygon.len() - 1 {
+                    vertex_indices.extend_from_slice(&[polygon[0], polygon[i], polygon[i + 1]]);
+                }
+            }
+        }
+    }
+
+    if let Some(&index) = vertex_indices.iter().find(|&&i| i >= vertices.len() / 3) {
+        return Err(format!("vertex index {} is out of range", index));
+    }
+
+    let mut model = Model::from_vectors(vertices, vertex_indices);
+    if has_colors {
+        model.colors = colors;
+    }
+
+    Ok(model)
+}
+
+impl Model {
+    /// Reads an ASCII or binary PLY file, including any per-vertex colors.
+    pub fn from_ply(filename: &str) -> Result<Self, RangleError> {
+        let bytes = fs::read(filename)?;
+
+        let ply_error = |reason: String| RangleError::PlyParse {
+            filename: filename.to_string(),
+            reason,
+        };
+
+        let header_end = bytes
+            .windows(b"end_header".len())
+            .position(|w| w == b"end_header")
+            .ok_or_else(|| ply_error("missing `end_header`".to_string()))?;
+        let body_start = match bytes[header_end..].iter().position(|&b| b == b'\n') {
+            Some(v) => header_end + v + 1,
+            None => bytes.len(),
+        };
+
+        let header = String::from_utf8_lossy(&bytes[..header_end]);
+        let lines: Vec<&str> = header.lines().collect();
+        let (format, elements) = parse_header(&lines).map_err(ply_error)?;
+
+        let body = &bytes[body_start..];
+        let text;
+        let tokens = if format == PlyFormat::Ascii {
+            text = String::from_utf8_lossy(body);
+            Some(text.split_ascii_whitespace())
+        } else {
+            None
+        };
+
+        let mut reader = PlyReader {
+            format,
+            bytes: body,
+            position: 0,
+            tokens,
+        };
+
+        read_body(&mut reader, &elements).map_err(ply_error)
+    }
+
+    /// Writes the model as a binary little endian PLY file, including any vertex colors.
+    pub fn write_ply(&self, filename: &str, bake_transform: bool) -> Result<(), RangleError> {
+        let mut writer = BufWriter::new(File::create(filename)?);
+
+        let vertices = self.get_export_vertices(bake_transform);
+
+        writeln!(writer, "ply")?;
+        writeln!(writer, "format binary_little_endian 1.0")?;
+        writeln!(writer, "comment Written by rangle")?;
+        writeln!(writer, "element vertex {}", vertices.len())?;
+        writeln!(writer, "property float x")?;
+        writeln!(writer, "property float y")?;
+        writeln!(writer, "property float z")?;
+        if self.has_vertex_colors() {
+            writeln!(writer, "property uchar red")?;
+            writeln!(writer, "property uchar green")?;
+            writeln!(writer, "property uchar blue")?;
+            writeln!(writer, "property uchar alpha")?;
+        }
+        writeln!(writer, "element face {}", self.vertex_indices.len() / 3)?;
+        writeln!(writer, "property list uchar uint vertex_indices")?;
+        writeln!(writer, "end_header")?;
+
+        for (i, v) in vertices.iter().enumerate() {
+            for c in &[v.x, v.y, v.z] {
+                writer.write_all(&c.to_le_bytes())?;
+            }
+
+            if self.has_vertex_colors() {
+                for &c in &self.colors[4 * i..4 * i + 4] {
+                    writer.write_all(&[(c.clamp(0.0, 1.0) * 255.0).round() as u8])?;
+                }
+            }
+        }
+
+        for triangle in self.vertex_indices.chunks(3) {
+            writer.write_all(&[3])?;
+            for &index in triangle {
+                writer.write_all(&(index as u32).to_le_bytes())?;
+            }
+        }
+
+        writer.flush()?;
+
+        Ok(())
+    }
+}
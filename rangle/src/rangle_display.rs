@@ -1,8 +1,34 @@
-use crate::{FrameBuffer, error::RangleError};
+use glam::Vec2;
+
+use crate::{FrameBuffer, LineCap, PointShape, error::RangleError};
 
 
 pub type Color = (u8, u8, u8, u8);
 
+/// A flat-colored shape in frame buffer coordinates, used for vector output.
+#[derive(Clone, Debug)]
+pub enum Primitive {
+    Polygon {
+        points: Vec<Vec2>,
+        color: Color,
+    },
+    /// A line `width` pixels wide.
+    Line {
+        start: Vec2,
+        end: Vec2,
+        width: f32,
+        cap: LineCap,
+        color: Color,
+    },
+    /// A point `size` pixels across.
+    Point {
+        position: Vec2,
+        size: f32,
+        shape: PointShape,
+        color: Color,
+    },
+}
+
 
 pub trait RangleDisplay {
     fn get_size(&self) -> (u16, u16);
@@ -12,4 +38,10 @@ pub trait RangleDisplay {
     fn set_background_color(&mut self, color: Color);
 
     fn draw_buffer(&self, frame_buffer: &FrameBuffer) -> Result<(), RangleError>;
+
+    /// Draws primitives in the given order, farthest first.
+    /// Displays which can only show pixels keep this default.
+    fn draw_primitives(&self, _primitives: &[Primitive]) -> Result<(), RangleError> {
+        Err(RangleError::VectorOutputUnsupported)
+    }
 }
@@ -0,0 +1,810 @@
+use std::{cell::RefCell, mem};
+
+use glam::{Vec2, Vec4, Vec4Swizzles};
+
+use crate::{
+    blend, rangle_display::Color, BlendMode, DepthState, LineCap, PipelineState, PointShape,
+    Rangle, Shader, StencilState, Transparency, Varyings,
+};
+
+/// The width and height of the screen regions rendered independently by each thread.
+pub(crate) const TILE_SIZE: usize = 32;
+
+/// Fractional bits kept of screen coordinates when rasterizing triangles.
+const SUBPIXEL_BITS: u32 = 8;
+
+/// A vertex ready to rasterize, in screen coordinates with pixel centers on whole numbers.
+#[derive(Clone)]
+pub(crate) struct RasterVertex {
+    pub(crate) position: Vec2,
+    /// The window depth compared against the depth buffer.
+    pub(crate) depth: f32,
+    pub(crate) val: Varyings,
+}
+
+pub(crate) enum RasterPrimitive {
+    Triangle([RasterVertex; 3]),
+    Line([RasterVertex; 2]),
+    /// A point and its size in render target pixels.
+    Point(RasterVertex, f32),
+}
+
+/// Where the colors of a primitive's fragments come from.
+pub(crate) enum Paint<'a> {
+    /// Runs the fragment shader on the interpolated varyings.
+    Shaded(&'a Shader),
+    /// Draws every fragment in one color, skipping the fragment shader.
+    Flat(Color),
+}
+
+impl Paint<'_> {
+    /// The color of a fragment, or `None` if the fragment shader discards it. `interpolate`
+    /// writes the fragment's varyings into `scratch`, and is only called when they are
+    /// shaded.
+    fn color(
+        &self,
+        scratch: &RefCell<Varyings>,
+        interpolate: impl FnOnce(&Shader, &mut Varyings),
+    ) -> Option<Color> {
+        match *self {
+            Paint::Shaded(shader) => {
+                let mut val = scratch.borrow_mut();
+                interpolate(shader, &mut val);
+
+                shader.run_fragment(&val).map(Rangle::vec_to_color)
+            }
+            Paint::Flat(color) => Some(color),
+        }
+    }
+}
+
+impl RasterPrimitive {
+    fn vertices(&self) -> &[RasterVertex] {
+        match self {
+            RasterPrimitive::Triangle(v) => v,
+            RasterPrimitive::Line(v) => v,
+            RasterPrimitive::Point(v, _) => std::slice::from_ref(v),
+        }
+    }
+
+    /// The average depth of the primitive's corners.
+    pub(crate) fn depth(&self) -> f32 {
+        let vertices = self.vertices();
+
+        vertices.iter().map(|vertex| vertex.depth).sum::<f32>() / vertices.len() as f32
+    }
+
+    /// The smallest and largest pixel coordinates the primitive can touch.
+    pub(crate) fn bounds(&self) -> (Vec2, Vec2) {
+        let vertices = self.vertices();
+
+        let mut min = vertices[0].position;
+        let mut max = vertices[0].position;
+        for vertex in &vertices[1..] {
+            min = min.min(vertex.position);
+            max = max.max(vertex.position);
+        }
+
+        // Lines round to the nearest pixel, and points spread around their vertex.
+        let reach = match self {
+            RasterPrimitive::Point(_, size) => Vec2::splat(size / 2.0),
+            _ => Vec2::ZERO,
+        };
+
+        ((min - reach).floor(), (max + reach).ceil())
+    }
+}
+
+/// The most samples a pixel can have.
+const MAX_SAMPLES: usize = 8;
+
+/// Sample positions in sixteenths of a pixel from its center, for each power of two sample
+/// count. These are the standard Direct3D multisample patterns.
+const SAMPLE_PATTERNS: [&[(i64, i64)]; 4] = [
+    &[(0, 0)],
+    &[(4, 4), (-4, -4)],
+    &[(-2, -6), (6, -2), (-6, 2), (2, 6)],
+    &[
+        (1, -3),
+        (-1, 3),
+        (5, 1),
+        (-3, -5),
+        (-5, 5),
+        (-7, -1),
+        (3, 7),
+        (7, -7),
+    ],
+];
+
+/// A rectangle of the frame, depth and stencil buffers, rendered on its own. Each pixel
+/// stores a color, depth and stencil value for every one of its samples until the tile is
+/// resolved, as well as the sums weighted blended transparency composites over that color.
+pub(crate) struct Tile {
+    pub(crate) x: usize,
+    pub(crate) y: usize,
+    pub(crate) width: usize,
+    pub(crate) height: usize,
+    samples: usize,
+    line_width: f32,
+    line_cap: LineCap,
+    smooth_lines: bool,
+    point_shape: PointShape,
+    depth: DepthState,
+    blend_mode: BlendMode,
+    transparency: Transparency,
+    /// Whether the second pass of sorted or weighted blended transparency is being drawn,
+    /// which draws the fragments that blend or have alpha below 1 respectively.
+    pub(crate) transparent_pass: bool,
+    /// The stencil state of the primitive being drawn.
+    pub(crate) stencil: StencilState,
+    pub(crate) colors: Vec<Color>,
+    pub(crate) depths: Vec<f32>,
+    pub(crate) stencils: Vec<u8>,
+    /// The weighted sums of transparent colors premultiplied by alpha, and of their alpha.
+    accumulated: Vec<Vec4>,
+    /// How much of the opaque color shows through the transparent fragments.
+    revealage: Vec<f32>,
+    /// Holds the varyings of the fragment being shaded, so that its storage is reused.
+    scratch: Varyings,
+}
+
+impl Tile {
+    pub(crate) fn new(
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+        background: Color,
+        state: &PipelineState,
+    ) -> Self {
+        let size = width * height * state.samples;
+        let transparent_size = match state.transparency {
+            Transparency::WeightedBlended => size,
+            _ => 0,
+        };
+
+        Tile {
+            x,
+            y,
+            width,
+            height,
+            samples: state.samples,
+            line_width: state.line_width,
+            line_cap: state.line_cap,
+            smooth_lines: state.smooth_lines,
+            point_shape: state.point_shape,
+            depth: state.depth,
+            blend_mode: state.blend_mode,
+            transparency: state.transparency,
+            transparent_pass: false,
+            stencil: StencilState::default(),
+            colors: vec![background; size],
+            depths: vec![state.depth.clear; size],
+            stencils: vec![0; size],
+            accumulated: vec![Vec4::ZERO; transparent_size],
+            revealage: vec![1.0; transparent_size],
+            scratch: Varyings::default(),
+        }
+    }
+
+    pub(crate) fn draw(&mut self, primitive: &RasterPrimitive, paint: &Paint) {
+        let scratch = RefCell::new(mem::take(&mut self.scratch));
+
+        match primitive {
+            RasterPrimitive::Triangle(v) => {
+                self.draw_triangle(&v[0], &v[1], &v[2], paint, &scratch)
+            }
+            RasterPrimitive::Line(v) => self.draw_line(&v[0], &v[1], paint, &scratch),
+            RasterPrimitive::Point(v, size) => self.draw_point(v, *size, paint, &scratch),
+        }
+
+        self.scratch = scratch.into_inner();
+    }
+
+    /// Composites transparent fragments over each sample, then averages the colors of each
+    /// pixel's samples, keeps the nearest of their depths and the stencil value of the
+    /// first, leaving one sample per pixel.
+    pub(crate) fn resolve(mut self) -> Self {
+        for (i, color) in self.colors.iter_mut().enumerate() {
+            match (self.accumulated.get(i), self.revealage.get(i)) {
+                (Some(&accumulated), Some(&revealage)) if revealage < 1.0 => {
+                    let opaque = Rangle::color_to_vec(*color);
+                    let average = accumulated.xyz() / accumulated.w.max(1e-5);
+
+                    *color = Rangle::vec_to_color(Vec4::from((
+                        average * (1.0 - revealage) + opaque.xyz() * revealage,
+                        1.0 - revealage + opaque.w * revealage,
+                    )));
+                }
+                _ => {}
+            }
+        }
+
+        if self.samples == 1 {
+            return self;
+        }
+
+        let n = self.samples as u32;
+        self.colors = self
+            .colors
+            .chunks(self.samples)
+            .map(|samples| {
+                let mut sum = (0, 0, 0, 0);
+                for &(r, g, b, a) in samples {
+                    sum = (
+                        sum.0 + r as u32,
+                        sum.1 + g as u32,
+                        sum.2 + b as u32,
+                        sum.3 + a as u32,
+                    );
+                }
+
+                (
+                    ((sum.0 + n / 2) / n) as u8,
+                    ((sum.1 + n / 2) / n) as u8,
+                    ((sum.2 + n / 2) / n) as u8,
+                    ((sum.3 + n / 2) / n) as u8,
+                )
+            })
+            .collect();
+        let depth = self.depth;
+        self.depths = self
+            .depths
+            .chunks(self.samples)
+            .map(|samples| {
+                samples
+                    .iter()
+                    .copied()
+                    .reduce(|a, b| depth.nearest(a, b))
+                    .unwrap()
+            })
+            .collect();
+        self.stencils = self
+            .stencils
+            .iter()
+            .step_by(self.samples)
+            .copied()
+            .collect();
+        self.samples = 1;
+
+        self
+    }
+
+    fn contains(&self, fx: i64, fy: i64) -> bool {
+        fx >= self.x as i64
+            && fy >= self.y as i64
+            && fx < (self.x + self.width) as i64
+            && fy < (self.y + self.height) as i64
+    }
+
+    /// Whether a fragment's color belongs to the pass being drawn. Sorted transparency
+    /// draws fragments which do not blend in its first pass, and weighted blended
+    /// transparency draws opaque fragments in its first pass, leaving the rest to the
+    /// second.
+    fn accepts(&self, color: Color) -> bool {
+        match self.transparency {
+            Transparency::Sorted => self.blend_mode.blends(color) == self.transparent_pass,
+            Transparency::WeightedBlended => (color.3 < 255) == self.transparent_pass,
+            _ => true,
+        }
+    }
+
+    /// Writes a fragment to a sample which passed the depth test, blending it into the
+    /// color by how much of the pixel the primitive covers.
+    fn write_sample(&mut self, index: usize, z: f32, color: Color, coverage: f32) {
+        if self.transparent_pass && self.transparency == Transparency::WeightedBlended {
+            let color = Rangle::color_to_vec(color);
+            let alpha = color.w * coverage;
+            let weight = blend::weight(alpha, self.depth.distance(z));
+
+            self.accumulated[index] += Vec4::from((color.xyz() * alpha, alpha)) * weight;
+            self.revealage[index] *= 1.0 - alpha;
+
+            return;
+        }
+
+        if coverage >= 0.5 && self.depth.write {
+            self.depths[index] = z;
+        }
+
+        let blended = self.blend_mode.blend(self.colors[index], color);
+        self.colors[index] = if coverage >= 1.0 {
+            blended
+        } else {
+            mix(self.colors[index], blended, coverage)
+        };
+    }
+
+    /// Whether a fragment at depth `z` passes the stencil test and the depth test of a
+    /// sample.
+    fn test(&self, index: usize, z: f32) -> (bool, bool) {
+        (
+            self.stencil.passes(self.stencils[index]),
+            self.depth.compare.passes(z, self.depths[index]),
+        )
+    }
+
+    fn update_stencil(&mut self, index: usize, (stencil_passed, depth_passed): (bool, bool)) {
+        self.stencils[index] =
+            self.stencil
+                .update(self.stencils[index], stencil_passed, depth_passed);
+    }
+
+    /// Stencil and depth tests the samples of screen pixel (fx, fy) against the depths
+    /// `sample_depth` gives for those the primitive covers, running `shade` once for the
+    /// pixel if any pass or the stencil state updates samples which fail.
+    fn draw_pixel(
+        &mut self,
+        fx: i64,
+        fy: i64,
+        sample_depth: impl Fn(usize) -> Option<f32>,
+        shade: impl FnOnce() -> Option<Color>,
+    ) {
+        if !self.contains(fx, fy) {
+            return;
+        }
+
+        let start = ((fy as usize - self.y) * self.width + (fx as usize - self.x)) * self.samples;
+        let mut tests = [None; MAX_SAMPLES];
+        for (sample, test) in tests[..self.samples].iter_mut().enumerate() {
+            *test = sample_depth(sample).map(|z| (z, self.test(start + sample, z)));
+        }
+
+        let passed = tests
+            .iter()
+            .flatten()
+            .any(|&(_, test)| test == (true, true));
+        let covered = tests.iter().any(Option::is_some);
+        if !(passed || covered && self.stencil.updates_on_failure()) {
+            return;
+        }
+
+        // The buffers are only written once the fragment is known not to be discarded.
+        let color = match shade() {
+            Some(color) if self.accepts(color) => color,
+            _ => return,
+        };
+
+        for (sample, test) in tests.iter().enumerate() {
+            if let Some((z, test)) = *test {
+                self.update_stencil(start + sample, test);
+
+                if test == (true, true) {
+                    self.write_sample(start + sample, z, color, 1.0);
+                }
+            }
+        }
+    }
+
+    /// Draws a point as a square or round sprite `size` pixels across, over the pixels
+    /// whose centers it covers. The pixel under the point is always drawn, so that small
+    /// round points do not vanish between pixel centers. Sprites are shaded once.
+    fn draw_point(
+        &mut self,
+        vertex: &RasterVertex,
+        size: f32,
+        paint: &Paint,
+        scratch: &RefCell<Varyings>,
+    ) {
+        let (position, half) = (vertex.position, size / 2.0);
+        let center = (position - Vec2::splat(0.5)).ceil();
+
+        let min = (position - Vec2::splat(half)).ceil();
+        let max = (position + Vec2::splat(half)).ceil() - Vec2::ONE;
+        let fx_min = (min.x as i64).max(self.x as i64);
+        let fx_max = (max.x as i64).min((self.x + self.width) as i64 - 1);
+        let fy_min = (min.y as i64).max(self.y as i64);
+        let fy_max = (max.y as i64).min((self.y + self.height) as i64 - 1);
+
+        let mut color = None;
+        for fy in fy_min..=fy_max {
+            for fx in fx_min..=fx_max {
+                let pixel = Vec2::new(fx as f32, fy as f32);
+                let inside = match self.point_shape {
+                    PointShape::Square => true,
+                    PointShape::Round => (pixel - position).length() < half || pixel == center,
+                };
+
+                if inside {
+                    self.draw_pixel(
+                        fx,
+                        fy,
+                        |_| Some(vertex.depth),
+                        || {
+                            *color.get_or_insert_with(|| {
+                                paint.color(scratch, |shader, val| {
+                                    shader.interpolate_into(&[&vertex.val], &[1.0], &[1.0], val)
+                                })
+                            })
+                        },
+                    );
+                }
+            }
+        }
+    }
+
+    /// Tests screen pixel (fx, fy) like `draw_pixel`, but mixes the color into what is
+    /// already there by how much of the pixel the primitive covers. Only mostly covered
+    /// pixels write their depth and stencil, so faint edges do not hide or mask what is
+    /// drawn behind them later.
+    fn blend_pixel(
+        &mut self,
+        fx: i64,
+        fy: i64,
+        z: f32,
+        coverage: f32,
+        shade: impl FnOnce() -> Option<Color>,
+    ) {
+        if coverage <= 0.0 || !self.contains(fx, fy) {
+            return;
+        }
+
+        let start = ((fy as usize - self.y) * self.width + (fx as usize - self.x)) * self.samples;
+        let mut shade = Some(shade);
+        let mut color = None;
+        for index in start..start + self.samples {
+            let test = self.test(index, z);
+            if test != (true, true) && !self.stencil.updates_on_failure() {
+                continue;
+            }
+
+            let color = match *color.get_or_insert_with(|| shade.take().unwrap()()) {
+                Some(color) if self.accepts(color) => color,
+                _ => return,
+            };
+
+            if coverage >= 0.5 {
+                self.update_stencil(index, test);
+            }
+            if test == (true, true) {
+                self.write_sample(index, z, color, coverage.min(1.0));
+            }
+        }
+    }
+
+    fn draw_line(
+        &mut self,
+        v1: &RasterVertex,
+        v2: &RasterVertex,
+        paint: &Paint,
+        scratch: &RefCell<Varyings>,
+    ) {
+        let shade = |k: f32| {
+            paint.color(scratch, |shader, val| {
+                Rangle::interpolate_line(&v1.val, &v2.val, k, shader, val)
+            })
+        };
+
+        if self.line_width > 1.0 {
+            self.draw_wide_line(v1, v2, shade);
+        } else if self.smooth_lines {
+            self.draw_wu_line(v1, v2, shade);
+        } else {
+            self.draw_thin_line(v1, v2, shade);
+        }
+    }
+
+    /// Steps one pixel at a time along the longer axis of the line.
+    fn draw_thin_line(
+        &mut self,
+        v1: &RasterVertex,
+        v2: &RasterVertex,
+        shade: impl Fn(f32) -> Option<Color>,
+    ) {
+        let (p1, p2) = (v1.position.round(), v2.position.round());
+        let d = p2 - p1;
+
+        let steps = d.x.abs().max(d.y.abs()) as i64;
+        for step in 0..=steps {
+            let k = if steps == 0 {
+                0.0
+            } else {
+                step as f32 / steps as f32
+            };
+
+            let p = (p1 + d * k).round();
+            let z = v1.depth * (1.0 - k) + v2.depth * k;
+
+            self.draw_pixel(p.x as i64, p.y as i64, |_| Some(z), || shade(k));
+        }
+    }
+
+    /// Draws a one pixel wide line with Xiaolin Wu's algorithm, which splits each step
+    /// along the longer axis between the two pixels nearest the line.
+    fn draw_wu_line(
+        &mut self,
+        v1: &RasterVertex,
+        v2: &RasterVertex,
+        shade: impl Fn(f32) -> Option<Color>,
+    ) {
+        // Work as if the line were mostly horizontal and ran left to right.
+        let steep = (v2.position.y - v1.position.y).abs() > (v2.position.x - v1.position.x).abs();
+        let swap_axes = |p: Vec2| if steep { Vec2::new(p.y, p.x) } else { p };
+        let (mut a, mut b) = (swap_axes(v1.position), swap_axes(v2.position));
+        let reversed = a.x > b.x;
+        if reversed {
+            std::mem::swap(&mut a, &mut b);
+        }
+
+        let d = b - a;
+        let gradient = if d.x == 0.0 { 0.0 } else { d.y / d.x };
+        let fract = |v: f32| v - v.floor();
+
+        let (x_start, x_end) = (a.x.round() as i64, b.x.round() as i64);
+        for x in x_start..=x_end {
+            let t = if d.x == 0.0 {
+                0.0
+            } else {
+                ((x as f32 - a.x) / d.x).clamp(0.0, 1.0)
+            };
+            let k = if reversed { 1.0 - t } else { t };
+            let z = v1.depth * (1.0 - k) + v2.depth * k;
+
+            // The end pixels are only as covered as the part of them the line reaches.
+            let gap = if x_start == x_end {
+                d.x
+            } else if x == x_start {
+                1.0 - fract(a.x + 0.5)
+            } else if x == x_end {
+                fract(b.x + 0.5)
+            } else {
+                1.0
+            };
+
+            let y = a.y + gradient * (x as f32 - a.x);
+            let y_floor = y.floor();
+            for &(minor, coverage) in &[
+                (y_floor, (1.0 - (y - y_floor)) * gap),
+                (y_floor + 1.0, (y - y_floor) * gap),
+            ] {
+                let (fx, fy) = if steep {
+                    (minor as i64, x)
+                } else {
+                    (x, minor as i64)
+                };
+
+                self.blend_pixel(fx, fy, z, coverage, || shade(k));
+            }
+        }
+    }
+
+    /// Draws a line wider than a pixel as a rectangle, or a capsule with round caps, over
+    /// the pixels whose centers it covers. Smoothed lines instead cover the pixels near
+    /// their edges by how far inside the centers are.
+    fn draw_wide_line(
+        &mut self,
+        v1: &RasterVertex,
+        v2: &RasterVertex,
+        shade: impl Fn(f32) -> Option<Color>,
+    ) {
+        let half = self.line_width / 2.0;
+        let (a, b) = (v1.position, v2.position);
+        let length = (b - a).length();
+        let direction = if length > 0.0 {
+            (b - a) / length
+        } else {
+            Vec2::new(1.0, 0.0)
+        };
+        let normal = Vec2::new(-direction.y, direction.x);
+        let extension = match self.line_cap {
+            LineCap::Square => half,
+            LineCap::Butt | LineCap::Round => 0.0,
+        };
+
+        let reach = Vec2::splat(self.line_width + 1.0);
+        let min = (a.min(b) - reach).floor();
+        let max = (a.max(b) + reach).ceil();
+        let fx_min = (min.x as i64).max(self.x as i64);
+        let fx_max = (max.x as i64).min((self.x + self.width) as i64 - 1);
+        let fy_min = (min.y as i64).max(self.y as i64);
+        let fy_max = (max.y as i64).min((self.y + self.height) as i64 - 1);
+
+        for fy in fy_min..=fy_max {
+            for fx in fx_min..=fx_max {
+                let offset = Vec2::new(fx as f32, fy as f32) - a;
+                let (along, across) = (offset.dot(direction), offset.dot(normal));
+                // The distance to the nearest point of the middle of the line.
+                let spine = (offset - direction * along.clamp(0.0, length)).length();
+
+                let coverage = if self.smooth_lines {
+                    let distance = match self.line_cap {
+                        LineCap::Round => spine - half,
+                        _ => (across.abs() - half)
+                            .max(-along - extension)
+                            .max(along - length - extension),
+                    };
+
+                    (0.5 - distance).clamp(0.0, 1.0)
+                } else {
+                    // Half-open intervals keep lines of even widths an even number of
+                    // pixels wide.
+                    let body = across > -half
+                        && across <= half
+                        && along >= -extension
+                        && along < length + extension;
+                    let inside = match self.line_cap {
+                        LineCap::Round => body || spine < half,
+                        _ => body,
+                    };
+
+                    if inside {
+                        1.0
+                    } else {
+                        0.0
+                    }
+                };
+
+                if coverage > 0.0 {
+                    let k = if length > 0.0 {
+                        (along / length).clamp(0.0, 1.0)
+                    } else {
+                        0.0
+                    };
+                    let z = v1.depth * (1.0 - k) + v2.depth * k;
+
+                    self.blend_pixel(fx, fy, z, coverage, || shade(k));
+                }
+            }
+        }
+    }
+
+    /// Rasterizes a triangle by stepping edge functions across the part of its bounding box
+    /// in this tile. Corners are snapped to `SUBPIXEL_BITS` of fixed-point precision, and
+    /// samples exactly on an edge belong to the triangle only if it is a top or left edge,
+    /// so triangles sharing an edge cover each sample exactly once.
+    fn draw_triangle(
+        &mut self,
+        v1: &RasterVertex,
+        v2: &RasterVertex,
+        v3: &RasterVertex,
+        paint: &Paint,
+        scratch: &RefCell<Varyings>,
+    ) {
+        let one = 1_i64 << SUBPIXEL_BITS;
+
+        let to_fixed = |p: Vec2| {
+            let s = p * one as f32;
+
+            (s.x.round() as i64, s.y.round() as i64)
+        };
+        let mut corners = [
+            to_fixed(v1.position),
+            to_fixed(v2.position),
+            to_fixed(v3.position),
+        ];
+        let mut vertices = [v1, v2, v3];
+
+        // Twice the signed area of triangle abp, positive when p is inside for a
+        // positively wound triangle.
+        let edge = |a: (i64, i64), b: (i64, i64), p: (i64, i64)| {
+            (b.0 - a.0) * (p.1 - a.1) - (b.1 - a.1) * (p.0 - a.0)
+        };
+
+        let mut area = edge(corners[0], corners[1], corners[2]);
+        if area == 0 {
+            return;
+        }
+        // Keep the first vertex first, since it provides the flat varyings.
+        if area < 0 {
+            corners.swap(1, 2);
+            vertices.swap(1, 2);
+            area = -area;
+        }
+
+        // Samples lie up to half a pixel from the pixel center.
+        let reach = if self.samples > 1 { one / 2 } else { 0 };
+        let x_min = corners.iter().map(|c| c.0).min().unwrap() - reach;
+        let x_max = corners.iter().map(|c| c.0).max().unwrap() + reach;
+        let y_min = corners.iter().map(|c| c.1).min().unwrap() - reach;
+        let y_max = corners.iter().map(|c| c.1).max().unwrap() + reach;
+
+        // Pixel centers lie on whole coordinates.
+        let fx_min = ((x_min + one - 1) >> SUBPIXEL_BITS).max(self.x as i64);
+        let fx_max = (x_max >> SUBPIXEL_BITS).min((self.x + self.width) as i64 - 1);
+        let fy_min = ((y_min + one - 1) >> SUBPIXEL_BITS).max(self.y as i64);
+        let fy_max = (y_max >> SUBPIXEL_BITS).min((self.y + self.height) as i64 - 1);
+        if fx_min > fx_max || fy_min > fy_max {
+            return;
+        }
+
+        let pattern = SAMPLE_PATTERNS[self.samples.trailing_zeros() as usize];
+
+        let mut row = [0; 3];
+        let mut bias = [0; 3];
+        let mut x_step = [0; 3];
+        let mut y_step = [0; 3];
+        let mut sample_offsets = [[0; 3]; MAX_SAMPLES];
+        let start = (fx_min << SUBPIXEL_BITS, fy_min << SUBPIXEL_BITS);
+        for k in 0..3 {
+            // The edge opposite corner k, whose function is corner k's barycentric weight.
+            let (a, b) = (corners[(k + 1) % 3], corners[(k + 2) % 3]);
+            let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+
+            let top_left = dy < 0 || (dy == 0 && dx > 0);
+            bias[k] = if top_left { 0 } else { -1 };
+
+            row[k] = edge(a, b, start) + bias[k];
+            x_step[k] = -dy * one;
+            y_step[k] = dx * one;
+
+            for (offsets, &(ox, oy)) in sample_offsets.iter_mut().zip(pattern) {
+                offsets[k] = (-dy * ox + dx * oy) * one / 16;
+            }
+        }
+
+        let area = area as f64;
+        let weights = |e: [i64; 3]| {
+            if e[0] >= 0 && e[1] >= 0 && e[2] >= 0 {
+                let a = ((e[0] - bias[0]) as f64 / area) as f32;
+                let b = ((e[1] - bias[1]) as f64 / area) as f32;
+
+                Some((a, b, 1.0 - a - b))
+            } else {
+                None
+            }
+        };
+        let depth = |(a, b, c): (f32, f32, f32)| {
+            vertices[0].depth * a + vertices[1].depth * b + vertices[2].depth * c
+        };
+
+        for fy in fy_min..=fy_max {
+            let mut e = row;
+            for fx in fx_min..=fx_max {
+                let mut sample_weights = [None; MAX_SAMPLES];
+                for (sample, offsets) in sample_offsets[..pattern.len()].iter().enumerate() {
+                    sample_weights[sample] =
+                        weights([e[0] + offsets[0], e[1] + offsets[1], e[2] + offsets[2]]);
+                }
+
+                // Shading happens at the pixel center if the triangle covers it, and
+                // otherwise at a covered sample, so varyings are never extrapolated.
+                let shading_weights = match pattern.len() {
+                    1 => sample_weights[0],
+                    _ => weights(e).or_else(|| sample_weights.iter().flatten().next().copied()),
+                };
+
+                // Depth is affine in screen space, so it is tested before interpolating
+                // anything else.
+                if let Some((a, b, c)) = shading_weights {
+                    self.draw_pixel(
+                        fx,
+                        fy,
+                        |sample| sample_weights[sample].map(depth),
+                        || {
+                            paint.color(scratch, |shader, val| {
+                                Rangle::interpolate_triangle(
+                                    (a, b, c),
+                                    &vertices[0].val,
+                                    &vertices[1].val,
+                                    &vertices[2].val,
+                                    shader,
+                                    val,
+                                )
+                            })
+                        },
+                    );
+                }
+
+                for k in 0..3 {
+                    e[k] += x_step[k];
+                }
+            }
+
+            for k in 0..3 {
+                row[k] += y_step[k];
+            }
+        }
+    }
+}
+
+/// Mixes a fraction `k` of color `b` into color `a`.
+fn mix(a: Color, b: Color, k: f32) -> Color {
+    let channel = |a: u8, b: u8| (a as f32 + (b as f32 - a as f32) * k).round() as u8;
+
+    (
+        channel(a.0, b.0),
+        channel(a.1, b.1),
+        channel(a.2, b.2),
+        channel(a.3, b.3),
+    )
+}
@@ -0,0 +1,145 @@
+use std::f32::consts::PI;
+
+use glam::Vec4;
+
+use crate::{rangle_display::Color, DepthState, Rangle, ResolveFilter};
+
+/// How many lobes of the sinc function the Lanczos filter keeps on each side.
+const LANCZOS_LOBES: f32 = 2.0;
+
+fn lanczos(x: f32) -> f32 {
+    if x == 0.0 {
+        1.0
+    } else if x.abs() < LANCZOS_LOBES {
+        LANCZOS_LOBES * (PI * x).sin() * (PI * x / LANCZOS_LOBES).sin() / (PI * PI * x * x)
+    } else {
+        0.0
+    }
+}
+
+/// Shrinks an image `scale` times larger than `size` along each axis down to `size`.
+pub(crate) fn downsample_colors(
+    colors: &[Color],
+    size: (usize, usize),
+    scale: usize,
+    filter: ResolveFilter,
+) -> Vec<Color> {
+    match filter {
+        ResolveFilter::Box => downsample_box(colors, size, scale),
+        ResolveFilter::Lanczos => downsample_lanczos(colors, size, scale),
+    }
+}
+
+/// Keeps the nearest depth of the pixels each output pixel covers.
+pub(crate) fn downsample_depths(
+    depths: &[f32],
+    (width, height): (usize, usize),
+    scale: usize,
+    depth: &DepthState,
+) -> Vec<f32> {
+    let mut rv: Vec<Option<f32>> = vec![None; width * height];
+    for y in 0..height * scale {
+        for x in 0..width * scale {
+            let index = (y / scale) * width + x / scale;
+            let sample = depths[y * width * scale + x];
+
+            rv[index] = Some(rv[index].map_or(sample, |nearest| depth.nearest(nearest, sample)));
+        }
+    }
+
+    rv.into_iter().flatten().collect()
+}
+
+/// Keeps the stencil value of the first pixel each output pixel covers.
+pub(crate) fn downsample_stencils(
+    stencils: &[u8],
+    (width, height): (usize, usize),
+    scale: usize,
+) -> Vec<u8> {
+    (0..height)
+        .flat_map(|y| (0..width).map(move |x| stencils[y * scale * width * scale + x * scale]))
+        .collect()
+}
+
+fn downsample_box(colors: &[Color], (width, height): (usize, usize), scale: usize) -> Vec<Color> {
+    let mut sums = vec![Vec4::ZERO; width * height];
+    for y in 0..height * scale {
+        for x in 0..width * scale {
+            sums[(y / scale) * width + x / scale] +=
+                Rangle::color_to_vec(colors[y * width * scale + x]);
+        }
+    }
+
+    let n = (scale * scale) as f32;
+    sums.into_iter()
+        .map(|sum| Rangle::vec_to_color(sum / n))
+        .collect()
+}
+
+/// The first pixel and the weights of the pixels along one axis which filter into each
+/// output pixel, normalized so that flat areas keep their color up to the image edges.
+fn lanczos_taps(size: usize, scale: usize) -> Vec<(usize, Vec<f32>)> {
+    let radius = LANCZOS_LOBES * scale as f32;
+    let last = (size * scale - 1) as f32;
+
+    (0..size)
+        .map(|i| {
+            // Where the output pixel center falls among the pixel centers being filtered.
+            let center = (i as f32 + 0.5) * scale as f32 - 0.5;
+            let first = (center - radius).ceil().max(0.0);
+            let end = (center + radius).floor().min(last);
+
+            let mut weights: Vec<f32> = (first as usize..=end as usize)
+                .map(|x| lanczos((x as f32 - center) / scale as f32))
+                .collect();
+            let sum: f32 = weights.iter().sum();
+            for weight in &mut weights {
+                *weight /= sum;
+            }
+
+            (first as usize, weights)
+        })
+        .collect()
+}
+
+/// Filters rows and then columns, which gives the same result as the full two
+/// dimensional filter for far fewer taps.
+fn downsample_lanczos(
+    colors: &[Color],
+    (width, height): (usize, usize),
+    scale: usize,
+) -> Vec<Color> {
+    let source_width = width * scale;
+    let source_height = height * scale;
+
+    let columns = lanczos_taps(width, scale);
+    let mut rows_filtered = vec![Vec4::ZERO; width * source_height];
+    for y in 0..source_height {
+        for (x, (first, weights)) in columns.iter().enumerate() {
+            rows_filtered[y * width + x] =
+                weights
+                    .iter()
+                    .enumerate()
+                    .fold(Vec4::ZERO, |sum, (i, &weight)| {
+                        sum + Rangle::color_to_vec(colors[y * source_width + first + i]) * weight
+                    });
+        }
+    }
+
+    let rows = lanczos_taps(height, scale);
+    let mut rv = Vec::with_capacity(width * height);
+    for (first, weights) in &rows {
+        for x in 0..width {
+            let color = weights
+                .iter()
+                .enumerate()
+                .fold(Vec4::ZERO, |sum, (i, &weight)| {
+                    sum + rows_filtered[(first + i) * width + x] * weight
+                });
+
+            rv.push(Rangle::vec_to_color(color));
+        }
+    }
+
+    rv
+}
@@ -1,122 +1,603 @@
-use std::{cell::RefCell, collections::HashMap, rc::Rc};
+use std::{
+    marker::PhantomData,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex, RwLock,
+    },
+};
 
 use glam::{Mat2, Mat3, Mat4, Vec2, Vec3, Vec4};
 
 use crate::error::RangleError;
 
+/// A type shaders can take as an attribute, varying or uniform. Values are stored as their
+/// components, so every one of them can be interpolated.
+pub trait ShaderValue: Copy + Send + Sync + 'static {
+    /// The name of the type, for errors.
+    const NAME: &'static str;
+    /// How many components the type has.
+    const SIZE: usize;
+
+    fn read(components: &[f32]) -> Self;
+    fn write(self, components: &mut [f32]);
+}
+
+impl ShaderValue for f32 {
+    const NAME: &'static str = "Float";
+    const SIZE: usize = 1;
+
+    fn read(components: &[f32]) -> Self {
+        components[0]
+    }
+
+    fn write(self, components: &mut [f32]) {
+        components[0] = self;
+    }
+}
+
+macro_rules! impl_shader_value {
+    ($t:ty, $size:expr, $read:ident, $write:ident) => {
+        impl ShaderValue for $t {
+            const NAME: &'static str = stringify!($t);
+            const SIZE: usize = $size;
+
+            fn read(components: &[f32]) -> Self {
+                <$t>::$read(components)
+            }
+
+            fn write(self, components: &mut [f32]) {
+                self.$write(components);
+            }
+        }
+    };
+}
+
+impl_shader_value!(Vec2, 2, from_slice, write_to_slice);
+impl_shader_value!(Vec3, 3, from_slice, write_to_slice);
+impl_shader_value!(Vec4, 4, from_slice, write_to_slice);
+impl_shader_value!(Mat2, 4, from_cols_slice, write_cols_to_slice);
+impl_shader_value!(Mat3, 9, from_cols_slice, write_cols_to_slice);
+impl_shader_value!(Mat4, 16, from_cols_slice, write_cols_to_slice);
+
+/// How a varying is blended across a primitive, after the GLSL qualifiers.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Interpolation {
+    /// Interpolated in clip space so it stays correct under perspective. The default.
+    Perspective,
+    /// Interpolated linearly in screen space.
+    NoPerspective,
+    /// Not interpolated; the first vertex of each primitive provides the value.
+    Flat,
+}
+
+/// Gives every layout its own id, so that handles can tell which layout they came from.
+static NEXT_LAYOUT_ID: AtomicUsize = AtomicUsize::new(1);
+
+/// A handle to a varying of type `T` in the layout it came from. Attributes are varyings
+/// which start out as the vertex's attribute values.
 #[derive(Clone, Copy, Debug)]
-pub enum ShaderType {
-    Float(f32),
-    Vec2(Vec2),
-    Vec3(Vec3),
-    Vec4(Vec4),
-    Mat2(Mat2),
-    Mat3(Mat3),
-    Mat4(Mat4),
+pub struct Varying<T> {
+    layout: usize,
+    offset: usize,
+    marker: PhantomData<T>,
+}
+
+/// A handle to a uniform of type `T` in the layout it came from.
+#[derive(Clone, Copy, Debug)]
+pub struct Uniform<T> {
+    layout: usize,
+    offset: usize,
+    marker: PhantomData<T>,
+}
+
+/// Records what a shader's functions do when `Shader::check` runs them.
+#[derive(Debug)]
+struct Probe {
+    /// Whether a handle from another layout was used.
+    foreign: AtomicBool,
+    /// Which varying components hold a value, either from an attribute or from the vertex
+    /// shader.
+    written: Mutex<Vec<bool>>,
+}
+
+impl Probe {
+    /// Handles the use of a handle from another layout. The checking run records it, so
+    /// that `Rangle::add_model` can fail, and anywhere else it is a bug in the shader.
+    fn foreign_handle(probe: &Option<Arc<Probe>>) {
+        match probe {
+            Some(probe) => probe.foreign.store(true, Ordering::Relaxed),
+            None => panic!("a shader used a varying or uniform from another layout"),
+        }
+    }
+
+    fn record_written(probe: &Option<Arc<Probe>>, offset: usize, size: usize) {
+        if let Some(probe) = probe {
+            probe.written.lock().unwrap()[offset..offset + size]
+                .iter_mut()
+                .for_each(|written| *written = true);
+        }
+    }
+}
+
+/// A named value in a layout, and where its components are stored.
+struct Declaration {
+    name: &'static str,
+    type_name: &'static str,
+    offset: usize,
+    size: usize,
+}
+
+impl Declaration {
+    fn range(&self) -> std::ops::Range<usize> {
+        self.offset..self.offset + self.size
+    }
 }
 
-pub type ShaderTypeMap = HashMap<&'static str, ShaderType>;
-type ShaderBuffer = Vec<ShaderType>;
-type VertexShaderFunction = fn(ShaderTypeMap, &ShaderTypeMap) -> ShaderTypeMap;
+/// Finds the declaration named `name`, checking that it holds a `T`.
+fn find<'a, T: ShaderValue>(
+    mut declarations: impl Iterator<Item = &'a Declaration>,
+    name: &'static str,
+) -> Result<usize, RangleError> {
+    let declaration = declarations
+        .find(|declaration| declaration.name == name)
+        .ok_or(RangleError::UnknownShaderValue(name))?;
+
+    if declaration.type_name != T::NAME {
+        return Err(RangleError::MismatchedShaderTypes {
+            name,
+            expected: T::NAME,
+            found: declaration.type_name,
+        });
+    }
+
+    Ok(declaration.offset)
+}
 
-#[derive(Clone)]
-struct VertexShader {
-    function: VertexShaderFunction,
-    attribute_buffers: HashMap<&'static str, ShaderBuffer>,
+/// The attributes, varyings and uniforms of a shader, declared before the shader is built
+/// so that its functions can capture the handles they read and write.
+pub struct ShaderLayout {
+    id: usize,
+    varyings: Vec<(Declaration, Interpolation)>,
+    varying_size: usize,
+    /// The name, varying offset and size of each attribute, and its values one vertex
+    /// after another.
+    attribute_buffers: Vec<(&'static str, usize, usize, Vec<f32>)>,
+    uniforms: Vec<Declaration>,
+    uniform_values: Vec<f32>,
 }
 
-impl VertexShader {
-    fn new(function: VertexShaderFunction) -> Self {
-        VertexShader {
-            function: function,
-            attribute_buffers: HashMap::new(),
+impl Default for ShaderLayout {
+    fn default() -> Self {
+        ShaderLayout {
+            id: NEXT_LAYOUT_ID.fetch_add(1, Ordering::Relaxed),
+            varyings: vec![],
+            varying_size: 0,
+            attribute_buffers: vec![],
+            uniforms: vec![],
+            uniform_values: vec![],
         }
     }
+}
+
+impl ShaderLayout {
+    pub fn new() -> Self {
+        ShaderLayout::default()
+    }
 
-    fn add_attribute(
+    pub fn add_attribute<T: ShaderValue>(
         &mut self,
         name: &'static str,
-        attribute_buffer: ShaderBuffer,
-    ) -> Result<(), RangleError> {
-        if self.attribute_buffers.get(name).is_some() {
+        attribute_buffer: Vec<T>,
+    ) -> Result<Varying<T>, RangleError> {
+        self.add_attribute_with_interpolation(name, attribute_buffer, Interpolation::Perspective)
+    }
+
+    pub fn add_attribute_with_interpolation<T: ShaderValue>(
+        &mut self,
+        name: &'static str,
+        attribute_buffer: Vec<T>,
+        interpolation: Interpolation,
+    ) -> Result<Varying<T>, RangleError> {
+        if self
+            .varyings
+            .iter()
+            .any(|(varying, _)| varying.name == name)
+        {
             return Err(RangleError::DuplicateShaderAttributes);
         }
 
-        self.attribute_buffers.insert(name, attribute_buffer);
+        let varying = self.add_varying_with_interpolation(name, interpolation)?;
 
-        Ok(())
+        let mut values = vec![0.0; attribute_buffer.len() * T::SIZE];
+        for (value, components) in attribute_buffer.into_iter().zip(values.chunks_mut(T::SIZE)) {
+            value.write(components);
+        }
+        self.attribute_buffers
+            .push((name, varying.offset, T::SIZE, values));
+
+        Ok(varying)
+    }
+
+    /// Declares a varying which the vertex shader sets, rather than passes through from an
+    /// attribute.
+    pub fn add_varying<T: ShaderValue>(
+        &mut self,
+        name: &'static str,
+    ) -> Result<Varying<T>, RangleError> {
+        self.add_varying_with_interpolation(name, Interpolation::Perspective)
     }
 
-    fn run(&self, index: usize, uniforms: &ShaderTypeMap) -> ShaderTypeMap {
-        let mut attributes = HashMap::new();
-        for (&k, v) in &self.attribute_buffers {
-            attributes.insert(k, v[index]);
+    pub fn add_varying_with_interpolation<T: ShaderValue>(
+        &mut self,
+        name: &'static str,
+        interpolation: Interpolation,
+    ) -> Result<Varying<T>, RangleError> {
+        if self
+            .varyings
+            .iter()
+            .any(|(varying, _)| varying.name == name)
+        {
+            return Err(RangleError::DuplicateShaderVaryings);
         }
 
-        (self.function)(attributes, uniforms)
+        let offset = self.varying_size;
+        self.varyings.push((
+            Declaration {
+                name,
+                type_name: T::NAME,
+                offset,
+                size: T::SIZE,
+            },
+            interpolation,
+        ));
+        self.varying_size += T::SIZE;
+
+        Ok(Varying {
+            layout: self.id,
+            offset,
+            marker: PhantomData,
+        })
     }
-}
 
-type FragmentShaderFunction = fn(ShaderTypeMap, &ShaderTypeMap) -> Vec4;
+    pub fn add_uniform<T: ShaderValue>(
+        &mut self,
+        name: &'static str,
+        uniform: T,
+    ) -> Result<Uniform<T>, RangleError> {
+        if self.uniforms.iter().any(|declared| declared.name == name) {
+            return Err(RangleError::DuplicateShaderUniforms);
+        }
+
+        let offset = self.uniform_values.len();
+        self.uniforms.push(Declaration {
+            name,
+            type_name: T::NAME,
+            offset,
+            size: T::SIZE,
+        });
+        self.uniform_values.resize(offset + T::SIZE, 0.0);
+        uniform.write(&mut self.uniform_values[offset..]);
+
+        Ok(Uniform {
+            layout: self.id,
+            offset,
+            marker: PhantomData,
+        })
+    }
+
+    /// Looks up an attribute or varying by name, failing if it is not a `T`.
+    pub fn varying<T: ShaderValue>(&self, name: &'static str) -> Result<Varying<T>, RangleError> {
+        Ok(Varying {
+            layout: self.id,
+            offset: find::<T>(self.varyings.iter().map(|(varying, _)| varying), name)?,
+            marker: PhantomData,
+        })
+    }
+
+    /// Looks up a uniform by name, failing if it is not a `T`.
+    pub fn uniform<T: ShaderValue>(&self, name: &'static str) -> Result<Uniform<T>, RangleError> {
+        Ok(Uniform {
+            layout: self.id,
+            offset: find::<T>(self.uniforms.iter(), name)?,
+            marker: PhantomData,
+        })
+    }
+}
 
-#[derive(Clone)]
-struct FragmentShader {
-    function: FragmentShaderFunction,
+/// The values a vertex shader passes on for one vertex, or their interpolation at a
+/// fragment.
+#[derive(Clone, Debug, Default)]
+pub struct Varyings {
+    /// The clip space position the vertex shader returned.
+    pub(crate) position: Vec4,
+    /// The size of the point at the vertex, if the vertex shader overrides it.
+    pub(crate) point_size: Option<f32>,
+    layout: usize,
+    values: Vec<f32>,
+    probe: Option<Arc<Probe>>,
 }
 
-impl FragmentShader {
-    fn new(function: FragmentShaderFunction) -> Self {
-        FragmentShader { function: function }
+impl Varyings {
+    /// Reads a varying, panicking if the handle comes from another layout.
+    /// `Rangle::add_model` reports that as an error instead when its check of the shader
+    /// reaches the read.
+    pub fn get<T: ShaderValue>(&self, varying: Varying<T>) -> T {
+        if varying.layout != self.layout {
+            Probe::foreign_handle(&self.probe);
+
+            return T::read(&[0.0; 16]);
+        }
+
+        T::read(&self.values[varying.offset..])
+    }
+
+    /// Writes a varying, panicking if the handle comes from another layout.
+    /// `Rangle::add_model` reports that as an error instead when its check of the shader
+    /// reaches the write.
+    pub fn set<T: ShaderValue>(&mut self, varying: Varying<T>, value: T) {
+        if varying.layout != self.layout {
+            Probe::foreign_handle(&self.probe);
+
+            return;
+        }
+
+        value.write(&mut self.values[varying.offset..]);
+        Probe::record_written(&self.probe, varying.offset, T::SIZE);
     }
 
-    fn run(&self, attributes: ShaderTypeMap, uniforms: &ShaderTypeMap) -> Vec4 {
-        (self.function)(attributes, uniforms)
+    /// The clip space position, interpolated like a perspective varying.
+    pub fn position(&self) -> Vec4 {
+        self.position
+    }
+
+    /// Sets the size of the point drawn at the vertex in frame buffer pixels, overriding
+    /// `Rangle::set_point_size`.
+    pub fn set_point_size(&mut self, size: f32) {
+        self.point_size = Some(size);
+    }
+}
+
+/// The values of a shader's uniforms.
+pub struct Uniforms {
+    layout: usize,
+    values: Vec<f32>,
+    probe: Option<Arc<Probe>>,
+}
+
+impl Uniforms {
+    /// Reads a uniform, panicking if the handle comes from another layout.
+    /// `Rangle::add_model` reports that as an error instead when its check of the shader
+    /// reaches the read.
+    pub fn get<T: ShaderValue>(&self, uniform: Uniform<T>) -> T {
+        if uniform.layout != self.layout {
+            Probe::foreign_handle(&self.probe);
+
+            return T::read(&[0.0; 16]);
+        }
+
+        T::read(&self.values[uniform.offset..])
     }
 }
 
+/// Takes the vertex's attributes as its varyings, sets any other varyings and returns the
+/// clip space position.
+type VertexShaderFunction = Box<dyn Fn(&mut Varyings, &Uniforms) -> Vec4 + Send + Sync>;
+
+/// Returns the fragment's color, or `None` to discard it.
+type FragmentShaderFunction = Box<dyn Fn(&Varyings, &Uniforms) -> Option<Vec4> + Send + Sync>;
+
 pub struct Shader {
-    vertex_shader: VertexShader,
-    fragment_shader: FragmentShader,
-    uniforms: ShaderTypeMap,
+    layout: ShaderLayout,
+    vertex_function: VertexShaderFunction,
+    fragment_function: FragmentShaderFunction,
+    uniforms: Uniforms,
 }
 
 impl Shader {
+    /// Builds a shader from its layout and its vertex and fragment functions. Closures can
+    /// capture the layout's handles and any other parameters, but have to be shareable
+    /// with the render threads.
     pub fn new(
-        vertex_function: VertexShaderFunction,
-        fragment_function: FragmentShaderFunction,
-    ) -> Rc<RefCell<Self>> {
-        Rc::new(RefCell::new(Shader {
-            vertex_shader: VertexShader::new(vertex_function),
-            fragment_shader: FragmentShader::new(fragment_function),
-            uniforms: HashMap::new(),
+        layout: ShaderLayout,
+        vertex_function: impl Fn(&mut Varyings, &Uniforms) -> Vec4 + Send + Sync + 'static,
+        fragment_function: impl Fn(&Varyings, &Uniforms) -> Option<Vec4> + Send + Sync + 'static,
+    ) -> Arc<RwLock<Self>> {
+        let uniforms = Uniforms {
+            layout: layout.id,
+            values: layout.uniform_values.clone(),
+            probe: None,
+        };
+
+        Arc::new(RwLock::new(Shader {
+            layout,
+            vertex_function: Box::new(vertex_function),
+            fragment_function: Box::new(fragment_function),
+            uniforms,
         }))
     }
 
-    pub fn add_attribute(
+    /// Sets a uniform, failing if the handle comes from another layout.
+    pub fn set_uniform<T: ShaderValue>(
         &mut self,
-        name: &'static str,
-        attribute_buffer: ShaderBuffer,
+        uniform: Uniform<T>,
+        value: T,
     ) -> Result<(), RangleError> {
-        self.vertex_shader.add_attribute(name, attribute_buffer)?;
+        if uniform.layout != self.layout.id {
+            return Err(RangleError::MismatchedShaderLayout);
+        }
+
+        value.write(&mut self.uniforms.values[uniform.offset..]);
 
         Ok(())
     }
 
-    pub fn set_uniform(
-        &mut self,
-        name: &'static str,
-        uniform: ShaderType,
-    ) -> Result<(), RangleError> {
-        self.uniforms.insert(name, uniform);
+    /// Looks up a uniform by name, failing if it is not a `T`.
+    pub fn uniform<T: ShaderValue>(&self, name: &'static str) -> Result<Uniform<T>, RangleError> {
+        self.layout.uniform(name)
+    }
+
+    /// Varyings laid out for this shader at clip space `position`, with every value zero.
+    pub(crate) fn new_varyings(&self, position: Vec4) -> Varyings {
+        Varyings {
+            position,
+            point_size: None,
+            layout: self.layout.id,
+            values: vec![0.0; self.layout.varying_size],
+            probe: None,
+        }
+    }
+
+    /// Checks that every attribute has a value for each of a model's `vertex_count`
+    /// vertices. Models can change after they are added, so this runs before every render.
+    pub(crate) fn check_attributes(&self, vertex_count: usize) -> Result<(), RangleError> {
+        for (name, _, size, values) in &self.layout.attribute_buffers {
+            if values.len() != vertex_count * size {
+                return Err(RangleError::MismatchedAttributeLength {
+                    name,
+                    expected: vertex_count,
+                    found: values.len() / size,
+                });
+            }
+        }
 
         Ok(())
     }
 
-    pub(crate) fn run_vertex(&self, index: usize) -> ShaderTypeMap {
-        self.vertex_shader.run(index, &self.uniforms)
+    /// Runs both functions once, on the first vertex, failing if they use a handle from
+    /// another layout, or if the vertex function returns a position which is not finite or
+    /// leaves a varying unset. Shaders for models without vertices are never run, so they
+    /// pass.
+    fn check_functions(&self, vertex_count: usize) -> Result<(), RangleError> {
+        if vertex_count == 0 {
+            return Ok(());
+        }
+
+        let probe = Arc::new(Probe {
+            foreign: AtomicBool::new(false),
+            written: Mutex::new(vec![false; self.layout.varying_size]),
+        });
+        let uniforms = Uniforms {
+            layout: self.layout.id,
+            values: self.uniforms.values.clone(),
+            probe: Some(probe.clone()),
+        };
+
+        let mut varyings = self.new_varyings(Vec4::ZERO);
+        varyings.probe = Some(probe.clone());
+        self.load_attributes(0, &mut varyings);
+        varyings.position = (self.vertex_function)(&mut varyings, &uniforms);
+
+        if probe.foreign.load(Ordering::Relaxed) {
+            return Err(RangleError::MismatchedShaderLayout);
+        }
+        if !varyings.position.is_finite() {
+            return Err(RangleError::NonFiniteShaderPosition);
+        }
+        let written = probe.written.lock().unwrap();
+        for (varying, _) in &self.layout.varyings {
+            if !written[varying.range()].iter().all(|&written| written) {
+                return Err(RangleError::UnsetShaderVarying(varying.name));
+            }
+        }
+        drop(written);
+
+        (self.fragment_function)(&varyings, &uniforms);
+
+        if probe.foreign.load(Ordering::Relaxed) {
+            return Err(RangleError::MismatchedShaderLayout);
+        }
+
+        Ok(())
+    }
+
+    /// Checks that the shader can run on a model with `vertex_count` vertices.
+    pub(crate) fn check(&self, vertex_count: usize) -> Result<(), RangleError> {
+        self.check_attributes(vertex_count)?;
+        self.check_functions(vertex_count)
+    }
+
+    /// Copies the attribute values of vertex `index` into its varyings.
+    fn load_attributes(&self, index: usize, varyings: &mut Varyings) {
+        for (_, offset, size, values) in &self.layout.attribute_buffers {
+            varyings.values[*offset..offset + size]
+                .copy_from_slice(&values[index * size..(index + 1) * size]);
+            Probe::record_written(&varyings.probe, *offset, *size);
+        }
     }
 
-    pub(crate) fn run_fragment(&self, attributes: ShaderTypeMap) -> Vec4 {
-        self.fragment_shader.run(attributes, &self.uniforms)
+    pub(crate) fn run_vertex(&self, index: usize) -> Varyings {
+        let mut varyings = self.new_varyings(Vec4::ZERO);
+        self.load_attributes(index, &mut varyings);
+
+        varyings.position = (self.vertex_function)(&mut varyings, &self.uniforms);
+
+        varyings
+    }
+
+    pub(crate) fn run_fragment(&self, varyings: &Varyings) -> Option<Vec4> {
+        (self.fragment_function)(varyings, &self.uniforms)
+    }
+
+    /// Blends the varyings of several vertices. Each varying uses whichever weights its
+    /// interpolation asks for, and flat varyings and the point size come from the first
+    /// vertex.
+    pub(crate) fn interpolate(
+        &self,
+        vals: &[&Varyings],
+        screen_weights: &[f32],
+        perspective_weights: &[f32],
+    ) -> Varyings {
+        let mut rv = Varyings::default();
+        self.interpolate_into(vals, screen_weights, perspective_weights, &mut rv);
+
+        rv
+    }
+
+    /// Like `interpolate`, but writes into `rv`, reusing its storage so that shading a
+    /// fragment does not allocate.
+    pub(crate) fn interpolate_into(
+        &self,
+        vals: &[&Varyings],
+        screen_weights: &[f32],
+        perspective_weights: &[f32],
+        rv: &mut Varyings,
+    ) {
+        let first = vals[0];
+
+        rv.position = Vec4::ZERO;
+        rv.point_size = first.point_size;
+        rv.layout = first.layout;
+        rv.values.clear();
+        rv.values.extend_from_slice(&first.values);
+        rv.probe = None;
+        for (val, &weight) in vals.iter().zip(perspective_weights) {
+            rv.position += val.position * weight;
+        }
+
+        for (varying, interpolation) in &self.layout.varyings {
+            let weights = match interpolation {
+                Interpolation::Flat => continue,
+                Interpolation::Perspective => perspective_weights,
+                Interpolation::NoPerspective => screen_weights,
+            };
+
+            for i in varying.range() {
+                rv.values[i] = vals
+                    .iter()
+                    .zip(weights)
+                    .map(|(val, &weight)| val.values[i] * weight)
+                    .sum();
+            }
+        }
+    }
+
+    /// Copies the flat varyings of `provoking` onto `val`, so that later interpolation keeps
+    /// them no matter which vertex it starts from.
+    pub(crate) fn apply_flat_varyings(&self, provoking: &Varyings, val: &mut Varyings) {
+        for (varying, interpolation) in &self.layout.varyings {
+            if *interpolation == Interpolation::Flat {
+                val.values[varying.range()].copy_from_slice(&provoking.values[varying.range()]);
+            }
+        }
     }
 }
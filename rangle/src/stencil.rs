@@ -0,0 +1,92 @@
+use crate::DepthCompare;
+
+/// What happens to a stencil value when a fragment is tested against it.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum StencilOp {
+    Keep,
+    Zero,
+    /// Writes the stencil state's reference value.
+    Replace,
+    IncrementClamp,
+    DecrementClamp,
+    IncrementWrap,
+    DecrementWrap,
+    Invert,
+}
+
+impl StencilOp {
+    fn apply(self, stored: u8, reference: u8) -> u8 {
+        match self {
+            StencilOp::Keep => stored,
+            StencilOp::Zero => 0,
+            StencilOp::Replace => reference,
+            StencilOp::IncrementClamp => stored.saturating_add(1),
+            StencilOp::DecrementClamp => stored.saturating_sub(1),
+            StencilOp::IncrementWrap => stored.wrapping_add(1),
+            StencilOp::DecrementWrap => stored.wrapping_sub(1),
+            StencilOp::Invert => !stored,
+        }
+    }
+}
+
+/// How a model's fragments are tested against and written to the 8-bit stencil buffer,
+/// which is cleared to 0 before each scene. The stencil test runs before the depth test,
+/// and fragments which fail either are not drawn.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct StencilState {
+    /// How the reference value is compared with the stored value, both masked by
+    /// `read_mask`. `DepthCompare::Less` passes where the reference is less than the
+    /// stored value.
+    pub compare: DepthCompare,
+    pub reference: u8,
+    pub read_mask: u8,
+    /// The bits of the stored value the operations below may change.
+    pub write_mask: u8,
+    /// Applied where the stencil test fails.
+    pub fail: StencilOp,
+    /// Applied where the stencil test passes but the depth test fails.
+    pub depth_fail: StencilOp,
+    /// Applied where both tests pass.
+    pub pass: StencilOp,
+}
+
+impl Default for StencilState {
+    /// Passes every fragment and leaves the stencil buffer untouched.
+    fn default() -> Self {
+        StencilState {
+            compare: DepthCompare::Always,
+            reference: 0,
+            read_mask: 0xff,
+            write_mask: 0xff,
+            fail: StencilOp::Keep,
+            depth_fail: StencilOp::Keep,
+            pass: StencilOp::Keep,
+        }
+    }
+}
+
+impl StencilState {
+    pub(crate) fn passes(&self, stored: u8) -> bool {
+        self.compare
+            .passes(self.reference & self.read_mask, stored & self.read_mask)
+    }
+
+    /// The stored value after a fragment's stencil and depth tests.
+    pub(crate) fn update(&self, stored: u8, stencil_passed: bool, depth_passed: bool) -> u8 {
+        let op = if !stencil_passed {
+            self.fail
+        } else if !depth_passed {
+            self.depth_fail
+        } else {
+            self.pass
+        };
+
+        (stored & !self.write_mask) | (op.apply(stored, self.reference) & self.write_mask)
+    }
+
+    /// Whether fragments which are not drawn can still change the stencil buffer, in which
+    /// case they have to be shaded to find out whether they are discarded.
+    pub(crate) fn updates_on_failure(&self) -> bool {
+        self.write_mask != 0 && (self.fail != StencilOp::Keep || self.depth_fail != StencilOp::Keep)
+    }
+}
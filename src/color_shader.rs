@@ -0,0 +1,34 @@
+use std::sync::{Arc, RwLock};
+
+use glam::Vec4;
+use rangle::{Camera, Model, Rangle, RangleError, Shader, ShaderLayout};
+
+pub fn get_color_shader(
+    width: u16,
+    height: u16,
+    color: (f32, f32, f32, f32),
+    model: &Model,
+) -> Result<Arc<RwLock<Shader>>, RangleError> {
+    let (x, y) = (width as f32, height as f32);
+
+    let m = model.compute_model_matrix();
+    let v = Camera::new(
+        (0.0, 0.0, 10.0).into(),
+        (0.0, 0.0, 0.0).into(),
+        (0.0, 1.0, 0.0).into(),
+    )
+    .compute_view_matrix();
+    let p = Rangle::compute_projection_matrix(45.0_f32.to_radians(), x / y, 0.1, 20.0);
+
+    let mvp = p * v * m;
+
+    let mut layout = ShaderLayout::new();
+    let position = layout.add_attribute("position", model.get_vertex_buffer())?;
+    let color = layout.add_attribute("color", model.get_color_buffer(color.into()))?;
+
+    Ok(Shader::new(
+        layout,
+        move |attributes, _uniforms| mvp * Vec4::from((attributes.get(position), 1.0)),
+        move |attributes, _uniforms| Some(attributes.get(color)),
+    ))
+}
@@ -1,20 +1,155 @@
-use rangle::{FrameBuffer, error::RangleError, rangle_display::{Color, RangleDisplay}};
+use std::collections::{BTreeMap, HashMap};
 
+use rangle::{
+    error::RangleError,
+    rangle_display::{Color, Primitive, RangleDisplay},
+    FrameBuffer, LineCap, PointShape,
+};
+
+/// An axis-aligned block of identically colored pixels, in pixel coordinates.
+#[derive(Clone, Copy)]
+struct Rectangle {
+    left: usize,
+    top: usize,
+    right: usize,
+    bottom: usize,
+}
 
 pub struct JgraphDisplay {
     width: u16,
     height: u16,
     background_color: (f32, f32, f32, f32),
+    quantize_levels: Option<u8>,
 }
 
 impl JgraphDisplay {
     pub fn new(width: u16, height: u16, color: (f32, f32, f32, f32)) -> Result<Self, RangleError> {
         Ok(JgraphDisplay {
-            width: width,
-            height: height,
+            width,
+            height,
             background_color: color,
+            quantize_levels: None,
         })
     }
+
+    /// Rounds each color channel to one of `levels` evenly spaced values before drawing,
+    /// so that more neighboring pixels can be merged.
+    pub fn set_quantize_levels(&mut self, levels: Option<u8>) {
+        self.quantize_levels = levels.map(|v| v.max(2));
+    }
+
+    fn quantize(&self, color: Color) -> Color {
+        let levels = match self.quantize_levels {
+            Some(v) => (v - 1) as f32,
+            None => return color,
+        };
+
+        let quantize_channel =
+            |c: u8| ((c as f32 / 255.0 * levels).round() / levels * 255.0).round() as u8;
+
+        (
+            quantize_channel(color.0),
+            quantize_channel(color.1),
+            quantize_channel(color.2),
+            color.3,
+        )
+    }
+
+    /// Merges the frame buffer into rectangles of identical color, leaving out the background.
+    /// Horizontal runs are found first, then runs covering the same columns in consecutive rows
+    /// are joined.
+    fn merge_pixels(
+        &self,
+        frame_buffer: &FrameBuffer,
+        background: Color,
+    ) -> Vec<(Color, Rectangle)> {
+        let (w, h) = (self.width as usize, self.height as usize);
+
+        let mut rv = vec![];
+        let mut open: HashMap<(usize, usize, Color), Rectangle> = HashMap::new();
+        let mut next_open = HashMap::new();
+        for i in 0..h {
+            let mut j = 0;
+            while j < w {
+                let color = self.pixel_color(frame_buffer, i, j, background);
+
+                let start = j;
+                while j + 1 < w && self.pixel_color(frame_buffer, i, j + 1, background) == color {
+                    j += 1;
+                }
+                let end = j;
+                j += 1;
+
+                if color == background {
+                    continue;
+                }
+
+                let rectangle = match open.remove(&(start, end, color)) {
+                    Some(v) => Rectangle { bottom: i, ..v },
+                    None => Rectangle {
+                        left: start,
+                        top: i,
+                        right: end,
+                        bottom: i,
+                    },
+                };
+                next_open.insert((start, end, color), rectangle);
+            }
+
+            rv.extend(
+                open.drain()
+                    .map(|((_, _, color), rectangle)| (color, rectangle)),
+            );
+            std::mem::swap(&mut open, &mut next_open);
+        }
+        rv.extend(
+            open.drain()
+                .map(|((_, _, color), rectangle)| (color, rectangle)),
+        );
+
+        rv
+    }
+
+    /// Prints the graph setup and the background, which is drawn once beneath everything else.
+    fn print_header(&self, background: Color) {
+        let (w, h) = self.get_size();
+
+        let (xsize_ps, ysize_ps) = (w as f32, h as f32);
+        let (xsize_in, ysize_in) = (xsize_ps / 72.0, ysize_ps / 72.0);
+
+        let paper_size = (612.0, 792.0);
+        let xtranslate = (paper_size.0 - xsize_ps) / 4.0;
+        let ytranslate = ((paper_size.1 - ysize_ps) / 4.0).round();
+
+        println!(
+            "bbox {} {} {} {}",
+            xtranslate,
+            ytranslate,
+            xsize_ps + xtranslate,
+            ysize_ps + ytranslate
+        );
+        println!("newgraph");
+        println!("xaxis nodraw min 0 max {} size {}", w, xsize_in);
+        println!("yaxis nodraw min 0 max {} size {}", h, ysize_in);
+
+        let center = ((w - 1) as f32 / 2.0, h as f32 - (h - 1) as f32 / 2.0);
+        print_box_curve(w as usize, h as usize, background, &[center]);
+    }
+
+    fn pixel_color(
+        &self,
+        frame_buffer: &FrameBuffer,
+        i: usize,
+        j: usize,
+        background: Color,
+    ) -> Color {
+        let color = frame_buffer.buffer[self.width as usize * i + j];
+        if color.3 == 0 {
+            return background;
+        }
+
+        self.quantize(color)
+    }
 }
 
 impl RangleDisplay for JgraphDisplay {
@@ -23,8 +158,8 @@ impl RangleDisplay for JgraphDisplay {
     }
 
     fn get_background_color(&self) -> Color {
-        let (r, b, g, a) = self.background_color;
-        
+        let (r, g, b, a) = self.background_color;
+
         (
             (r.max(0.0) * 255.0).round() as u8,
             (g.max(0.0) * 255.0).round() as u8,
@@ -43,46 +178,152 @@ impl RangleDisplay for JgraphDisplay {
     }
 
     fn draw_buffer(&self, frame_buffer: &FrameBuffer) -> Result<(), RangleError> {
-        let mut color;
+        let (_w, h) = self.get_size();
 
-        let (w, h) = self.get_size();
-        
-        let (xsize_ps, ysize_ps) = (w as f32, h as f32);
-        let (xsize_in, ysize_in) = (xsize_ps / 72.0, ysize_ps / 72.0);
+        let background = self.quantize(self.get_background_color());
+        self.print_header(background);
 
-        let paper_size = (612.0, 792.0);
-        let xtranslate = (paper_size.0 - xsize_ps) / 4.0;
-        let ytranslate = ((paper_size.1 - ysize_ps) / 4.0).round();
+        // Pixel (j, i) is a unit box centered on (j, height - i).
+        // Rectangles of the same size and color share a curve.
+        let mut curves: BTreeMap<(usize, usize, Color), Vec<(f32, f32)>> = BTreeMap::new();
+        for (color, rectangle) in self.merge_pixels(frame_buffer, background) {
+            let (rw, rh) = (
+                rectangle.right - rectangle.left + 1,
+                rectangle.bottom - rectangle.top + 1,
+            );
+            let x = (rectangle.left + rectangle.right) as f32 / 2.0;
+            let y = h as f32 - (rectangle.top + rectangle.bottom) as f32 / 2.0;
 
-        println!("bbox {} {} {} {}", xtranslate, ytranslate, xsize_ps+xtranslate, ysize_ps+ytranslate);
-        println!("newgraph");
-        println!("xaxis nodraw min 0 max {} size {}", w, xsize_in);
-        println!("yaxis nodraw min 0 max {} size {}", h, ysize_in);
-        for i in 0..self.height as usize {
-            for j in 0..self.width as usize {
-                color = frame_buffer.buffer[self.width as usize * i + j];
-                if color.3 == 0 {
-                    let (r, g, b, a) = self.background_color;
-                    color = (
-                        (r.max(0.0) * 255.0).round() as u8,
-                        (g.max(0.0) * 255.0).round() as u8,
-                        (b.max(0.0) * 255.0).round() as u8,
-                        (a.max(0.0) * 255.0).round() as u8,
+            curves.entry((rw, rh, color)).or_default().push((x, y));
+        }
+
+        for ((rw, rh, color), pts) in &curves {
+            print_box_curve(*rw, *rh, *color, pts);
+        }
+
+        Ok(())
+    }
+
+    fn draw_primitives(&self, primitives: &[Primitive]) -> Result<(), RangleError> {
+        let (_w, h) = self.get_size();
+        let h = h as f32;
+
+        self.print_header(self.quantize(self.get_background_color()));
+
+        // Primitives arrive farthest first, so each curve is printed in order to paint over
+        // the ones behind it.
+        for primitive in primitives {
+            match primitive {
+                Primitive::Polygon { points, color } => {
+                    if color.3 == 0 {
+                        continue;
+                    }
+
+                    // Outlining polygons in their own color hides seams between neighbors.
+                    let (r, g, b) = color_to_rgb(self.quantize(*color));
+                    print!("newcurve poly clip marktype none linetype solid linethickness 0.5 ");
+                    print!("pcfill {} {} {} color {} {} {} pts", r, g, b, r, g, b);
+                    for point in points {
+                        print!(" {:.2} {:.2}", point.x, h - point.y);
+                    }
+                    println!();
+                }
+                Primitive::Line {
+                    start,
+                    end,
+                    width,
+                    cap,
+                    color,
+                } => {
+                    if color.3 == 0 {
+                        continue;
+                    }
+
+                    // Jgraph lines end flat, so square caps lengthen the line and round caps
+                    // add a circle mark at each end.
+                    let (mut start, mut end) = (*start, *end);
+                    if *cap == LineCap::Square {
+                        let extension = (end - start).normalize_or_zero() * (width / 2.0);
+                        start -= extension;
+                        end += extension;
+                    }
+
+                    let (r, g, b) = color_to_rgb(self.quantize(*color));
+                    let marks = match cap {
+                        LineCap::Round => {
+                            format!(
+                                "circle marksize {} {} cfill {} {} {}",
+                                width, width, r, g, b
+                            )
+                        }
+                        _ => "none".to_string(),
+                    };
+                    println!(
+                        "newcurve clip marktype {} linetype solid linethickness {} color {} {} {} pts {:.2} {:.2} {:.2} {:.2}",
+                        marks,
+                        width,
+                        r,
+                        g,
+                        b,
+                        start.x,
+                        h - start.y,
+                        end.x,
+                        h - end.y
+                    );
+                }
+                Primitive::Point {
+                    position,
+                    size,
+                    shape,
+                    color,
+                } => {
+                    if color.3 == 0 {
+                        continue;
+                    }
+
+                    let mark = match shape {
+                        PointShape::Square => "box",
+                        PointShape::Round => "circle",
+                    };
+                    let (r, g, b) = color_to_rgb(self.quantize(*color));
+                    println!(
+                        "newcurve clip marktype {} marksize {} {} cfill {} {} {} color {} {} {} pts {:.2} {:.2}",
+                        mark,
+                        size,
+                        size,
+                        r,
+                        g,
+                        b,
+                        r,
+                        g,
+                        b,
+                        position.x,
+                        h - position.y
                     );
                 }
-                
-                let (r, g, b) = (
-                    color.0 as f32 / 255.0,
-                    color.1 as f32 / 255.0,
-                    color.2 as f32 / 255.0,
-                );
-
-                print!("newcurve marktype box marksize 1 1 ");
-                print!("color {} {} {} ", r, g, b);
-                print!("pts {} {} ", j, self.height as usize - i);
             }
         }
 
         Ok(())
     }
 }
+
+fn color_to_rgb(color: Color) -> (f32, f32, f32) {
+    (
+        color.0 as f32 / 255.0,
+        color.1 as f32 / 255.0,
+        color.2 as f32 / 255.0,
+    )
+}
+
+fn print_box_curve(rw: usize, rh: usize, color: Color, pts: &[(f32, f32)]) {
+    let (r, g, b) = color_to_rgb(color);
+
+    print!("newcurve marktype box marksize {} {} ", rw, rh);
+    print!("color {} {} {} ", r, g, b);
+    print!("pts");
+    for (x, y) in pts {
+        print!(" {} {}", x, y);
+    }
+    println!();
+}
@@ -0,0 +1,261 @@
+use std::sync::{Arc, RwLock};
+
+use glam::{Mat3, Mat4, Vec3, Vec4, Vec4Swizzles};
+use rangle::{
+    Camera, Model, Rangle, RangleError, Shader, ShaderLayout, Uniform, Uniforms, Varying, Varyings,
+};
+
+pub const MAX_LIGHTS: usize = 4;
+
+const LIGHT_POSITIONS: [&str; MAX_LIGHTS] = [
+    "lightPosition0",
+    "lightPosition1",
+    "lightPosition2",
+    "lightPosition3",
+];
+const LIGHT_COLORS: [&str; MAX_LIGHTS] =
+    ["lightColor0", "lightColor1", "lightColor2", "lightColor3"];
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum LightingModel {
+    /// Per-pixel diffuse lighting only
+    Lambert,
+    /// Blinn-Phong lighting computed per vertex and interpolated
+    Gouraud,
+    /// Per-pixel Phong lighting
+    Phong,
+    /// Per-pixel Blinn-Phong lighting
+    BlinnPhong,
+}
+
+#[derive(Clone, Copy)]
+pub enum Light {
+    /// A light infinitely far away, shining in `direction`
+    Directional { direction: Vec3, color: Vec3 },
+    /// A light at `position`, shining in every direction
+    Point { position: Vec3, color: Vec3 },
+}
+
+pub struct Lighting {
+    pub model: LightingModel,
+    pub lights: Vec<Light>,
+    pub ambient: Vec3,
+    pub shininess: f32,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Specular {
+    None,
+    Phong,
+    BlinnPhong,
+}
+
+/// The handles of the values every lighting model reads.
+#[derive(Clone, Copy)]
+struct LitLayout {
+    position: Varying<Vec3>,
+    normal: Varying<Vec3>,
+    color: Varying<Vec4>,
+    specular: Varying<Vec4>,
+    world_position: Varying<Vec3>,
+    world_normal: Varying<Vec3>,
+    mvp: Uniform<Mat4>,
+    model_matrix: Uniform<Mat4>,
+    normal_matrix: Uniform<Mat3>,
+    camera_position: Uniform<Vec3>,
+    ambient: Uniform<Vec3>,
+    light_count: Uniform<f32>,
+    light_positions: [Uniform<Vec4>; MAX_LIGHTS],
+    light_colors: [Uniform<Vec3>; MAX_LIGHTS],
+}
+
+/// Computes the color of a surface point lit by every light in the uniforms, from its
+/// world position and normal, its color, and its specular color in `xyz` with the
+/// shininess in `w`.
+fn shade(val: &Varyings, uniforms: &Uniforms, lit: &LitLayout, specular_model: Specular) -> Vec4 {
+    let position = val.get(lit.world_position);
+    let diffuse = val.get(lit.color);
+    let specular = val.get(lit.specular);
+    let to_camera = (uniforms.get(lit.camera_position) - position).normalize_or_zero();
+
+    // Light both sides of the surface, since not every model has consistent winding.
+    let mut normal = val.get(lit.world_normal).normalize_or_zero();
+    if normal.dot(to_camera) < 0.0 {
+        normal = -normal;
+    }
+
+    let mut color = uniforms.get(lit.ambient) * diffuse.xyz();
+
+    let light_count = uniforms.get(lit.light_count) as usize;
+    for i in 0..light_count {
+        let light_position = uniforms.get(lit.light_positions[i]);
+        let light_color = uniforms.get(lit.light_colors[i]);
+
+        // Directional lights store the direction towards the light with a w of 0.
+        let to_light = if light_position.w == 0.0 {
+            light_position.xyz().normalize_or_zero()
+        } else {
+            (light_position.xyz() - position).normalize_or_zero()
+        };
+
+        let lambert = normal.dot(to_light);
+        if lambert <= 0.0 {
+            continue;
+        }
+
+        color += light_color * diffuse.xyz() * lambert;
+
+        let highlight = match specular_model {
+            Specular::None => 0.0,
+            Specular::Phong => (2.0 * lambert * normal - to_light).dot(to_camera),
+            Specular::BlinnPhong => normal.dot((to_light + to_camera).normalize_or_zero()),
+        };
+        if highlight > 0.0 {
+            color += light_color * specular.xyz() * highlight.powf(specular.w);
+        }
+    }
+
+    Vec4::from((color, diffuse.w))
+}
+
+/// Transforms the position for the screen, and the position and normal into world space.
+fn lit_vertex(val: &mut Varyings, uniforms: &Uniforms, lit: &LitLayout) -> Vec4 {
+    let position = val.get(lit.position);
+    let normal = val.get(lit.normal);
+
+    val.set(
+        lit.world_position,
+        uniforms.get(lit.model_matrix).transform_point3(position),
+    );
+    val.set(lit.world_normal, uniforms.get(lit.normal_matrix) * normal);
+
+    uniforms.get(lit.mvp) * Vec4::from((position, 1.0))
+}
+
+pub fn get_lit_shader(
+    width: u16,
+    height: u16,
+    color: (f32, f32, f32, f32),
+    use_materials: bool,
+    lighting: &Lighting,
+    model: &Model,
+) -> Result<Arc<RwLock<Shader>>, RangleError> {
+    let vertices = model.get_vertex_buffer();
+    let normals = if model.has_normals() {
+        model.get_normal_buffer()
+    } else {
+        model.get_face_normal_buffer()
+    };
+
+    let default_specular = Vec4::new(0.5, 0.5, 0.5, lighting.shininess);
+    let (colors, speculars) = if use_materials {
+        let mut speculars = vec![];
+        for material in model.get_face_materials() {
+            let specular = match material {
+                Some(index) => {
+                    let material = &model.get_materials()[*index];
+                    Vec4::from((material.specular, material.shininess))
+                }
+                None => default_specular,
+            };
+
+            speculars.push(specular);
+            speculars.push(specular);
+            speculars.push(specular);
+        }
+
+        (model.get_material_color_buffer(color.into()), speculars)
+    } else {
+        (
+            vec![Vec4::from(color); vertices.len()],
+            vec![default_specular; vertices.len()],
+        )
+    };
+
+    let (x, y) = (width as f32, height as f32);
+
+    let camera_position = Vec3::new(0.0, 0.0, 10.0);
+    let m = model.compute_model_matrix();
+    let v = Camera::new(
+        camera_position,
+        (0.0, 0.0, 0.0).into(),
+        (0.0, 1.0, 0.0).into(),
+    )
+    .compute_view_matrix();
+    let p = Rangle::compute_projection_matrix(45.0_f32.to_radians(), x / y, 0.1, 20.0);
+
+    let mvp = p * v * m;
+    let normal_matrix = Mat3::from_cols(m.x_axis.xyz(), m.y_axis.xyz(), m.z_axis.xyz())
+        .inverse()
+        .transpose();
+
+    // Unused lights stay black.
+    let lights = &lighting.lights[..lighting.lights.len().min(MAX_LIGHTS)];
+    let mut light_positions = [Vec4::ZERO; MAX_LIGHTS];
+    let mut light_colors = [Vec3::ZERO; MAX_LIGHTS];
+    for (i, light) in lights.iter().enumerate() {
+        let (position, color) = match *light {
+            Light::Directional { direction, color } => (Vec4::from((-direction, 0.0)), color),
+            Light::Point { position, color } => (Vec4::from((position, 1.0)), color),
+        };
+
+        light_positions[i] = position;
+        light_colors[i] = color;
+    }
+
+    let mut layout = ShaderLayout::new();
+    let lit = LitLayout {
+        position: layout.add_attribute("position", vertices)?,
+        normal: layout.add_attribute("normal", normals)?,
+        color: layout.add_attribute("color", colors)?,
+        specular: layout.add_attribute("specular", speculars)?,
+        world_position: layout.add_varying("worldPosition")?,
+        world_normal: layout.add_varying("worldNormal")?,
+        mvp: layout.add_uniform("mvpMatrix", mvp)?,
+        model_matrix: layout.add_uniform("modelMatrix", m)?,
+        normal_matrix: layout.add_uniform("normalMatrix", normal_matrix)?,
+        camera_position: layout.add_uniform("cameraPosition", camera_position)?,
+        ambient: layout.add_uniform("ambient", lighting.ambient)?,
+        light_count: layout.add_uniform("lightCount", lights.len() as f32)?,
+        light_positions: [
+            layout.add_uniform(LIGHT_POSITIONS[0], light_positions[0])?,
+            layout.add_uniform(LIGHT_POSITIONS[1], light_positions[1])?,
+            layout.add_uniform(LIGHT_POSITIONS[2], light_positions[2])?,
+            layout.add_uniform(LIGHT_POSITIONS[3], light_positions[3])?,
+        ],
+        light_colors: [
+            layout.add_uniform(LIGHT_COLORS[0], light_colors[0])?,
+            layout.add_uniform(LIGHT_COLORS[1], light_colors[1])?,
+            layout.add_uniform(LIGHT_COLORS[2], light_colors[2])?,
+            layout.add_uniform(LIGHT_COLORS[3], light_colors[3])?,
+        ],
+    };
+
+    let per_pixel = |layout, specular_model| {
+        Shader::new(
+            layout,
+            move |val, uniforms| lit_vertex(val, uniforms, &lit),
+            move |val, uniforms| Some(shade(val, uniforms, &lit, specular_model)),
+        )
+    };
+
+    Ok(match lighting.model {
+        LightingModel::Gouraud => {
+            let lit_color = layout.add_varying("litColor")?;
+
+            Shader::new(
+                layout,
+                move |val, uniforms| {
+                    let position = lit_vertex(val, uniforms, &lit);
+                    val.set(lit_color, shade(val, uniforms, &lit, Specular::BlinnPhong));
+
+                    position
+                },
+                move |val, _uniforms| Some(val.get(lit_color)),
+            )
+        }
+        LightingModel::Lambert => per_pixel(layout, Specular::None),
+        LightingModel::Phong => per_pixel(layout, Specular::Phong),
+        LightingModel::BlinnPhong => per_pixel(layout, Specular::BlinnPhong),
+    })
+}
@@ -1,13 +1,30 @@
-use std::process::exit;
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+    process::exit,
+    thread,
+};
 
-use clap::{App, Arg};
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use glam::{Vec3, Vec4};
 
-use rangle::{Model, Rangle, RangleError, RangleMode};
+use rangle::{
+    AntiAliasing, BlendMode, CullMode, DepthState, FrontFace, LineCap, Model, PointShape, Rangle,
+    RangleError, RangleMode, RangleOutput, ResolveFilter, Transparency,
+};
 use solid_shader::get_solid_shader;
 
-use crate::{jgraph_display::JgraphDisplay, normal_shader::get_normal_shader};
+use crate::{
+    color_shader::get_color_shader,
+    jgraph_display::JgraphDisplay,
+    lit_shader::{get_lit_shader, Light, Lighting, LightingModel, MAX_LIGHTS},
+    normal_shader::get_normal_shader,
+};
 
+mod color_shader;
 mod jgraph_display;
+mod lit_shader;
 mod normal_shader;
 mod solid_shader;
 
@@ -52,23 +69,226 @@ fn match_vec3(color: Option<&str>) -> Result<(f32, f32, f32), String> {
         Err(e) => return Err(e.to_string()),
     };
 
-    match it.next() {
-        Some(_) => return Err("Too many values given".to_string()),
-        None => (),
-    };
+    if it.next().is_some() {
+        return Err("Too many values given".to_string());
+    }
 
     Ok((r, g, b))
 }
 
+/// Parses `none`, `ssaa:N` or `msaa:N`.
+fn match_anti_aliasing(aa: &str, filter: ResolveFilter) -> Result<AntiAliasing, String> {
+    match aa.split_once(':') {
+        None if aa == "none" => Ok(AntiAliasing::None),
+        Some(("ssaa", factor)) => match factor.parse::<u8>() {
+            Ok(factor) => Ok(AntiAliasing::Ssaa { factor, filter }),
+            Err(e) => Err(e.to_string()),
+        },
+        Some(("msaa", samples)) => match samples.parse::<u8>() {
+            Ok(samples) => Ok(AntiAliasing::Msaa { samples }),
+            Err(e) => Err(e.to_string()),
+        },
+        _ => Err(format!("Invalid anti-aliasing mode `{}`", aa)),
+    }
+}
+
+fn load_model(filename: &str) -> Result<Model, RangleError> {
+    let extension = Path::new(filename)
+        .extension()
+        .map(|v| v.to_string_lossy().to_lowercase());
+
+    match extension.as_deref() {
+        Some("stl") => Model::from_stl(filename),
+        Some("ply") => Model::from_ply(filename),
+        _ => Model::from_file(filename),
+    }
+}
+
+/// The arguments which select and transform the model, shared by rendering and conversion.
+fn model_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    vec![
+        Arg::with_name("object")
+            .long("object")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .value_name("NAME")
+            .help("Only keep the named object or group (may be repeated)"),
+        Arg::with_name("hide_object")
+            .long("hide-object")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .value_name("NAME")
+            .help("Leave out the named object or group (may be repeated)"),
+        Arg::with_name("scale")
+            .short("s")
+            .long("scale")
+            .takes_value(true)
+            .allow_hyphen_values(true)
+            .default_value("1 1 1")
+            .help("The x y z values to scale the model by"),
+        Arg::with_name("translate")
+            .short("t")
+            .long("translate")
+            .takes_value(true)
+            .allow_hyphen_values(true)
+            .default_value("0 0 0")
+            .help("The xyz values to translate the model by"),
+        Arg::with_name("yaw")
+            .long("yaw")
+            .takes_value(true)
+            .allow_hyphen_values(true)
+            .default_value("0")
+            .help("The value in radians to rotate the model around the y-axis"),
+        Arg::with_name("pitch")
+            .long("pitch")
+            .takes_value(true)
+            .allow_hyphen_values(true)
+            .default_value("0")
+            .help("The value in radians to rotate the model around the x-axis"),
+        Arg::with_name("roll")
+            .long("roll")
+            .takes_value(true)
+            .allow_hyphen_values(true)
+            .default_value("0")
+            .help("The value in radians to rotate the model around the z-axis"),
+        Arg::with_name("crease_angle")
+            .long("crease-angle")
+            .takes_value(true)
+            .value_name("DEGREES")
+            .help("Generate smooth normals, keeping edges sharper than this angle creased"),
+    ]
+}
+
+/// Loads the model named by the "filename" argument, then selects and transforms it.
+fn prepare_model(matches: &ArgMatches) -> Result<Model, RangleError> {
+    let filename = matches.value_of("filename").unwrap();
+    let objects: Vec<&str> = matches.values_of("object").map_or(vec![], |v| v.collect());
+    let hidden_objects: Vec<&str> = matches
+        .values_of("hide_object")
+        .map_or(vec![], |v| v.collect());
+    let scale = matches.value_of("scale");
+    let translate = matches.value_of("translate");
+    let yaw = matches.value_of("yaw").unwrap();
+    let pitch = matches.value_of("pitch").unwrap();
+    let roll = matches.value_of("roll").unwrap();
+    let crease_angle = matches.value_of("crease_angle");
+
+    let scale = match match_vec3(scale) {
+        Ok(v) => v,
+        Err(e) => {
+            eprintln!("{}", e);
+            exit(2);
+        }
+    };
+    let translate = match match_vec3(translate) {
+        Ok(v) => v,
+        Err(e) => {
+            eprintln!("{}", e);
+            exit(2);
+        }
+    };
+    let yaw = yaw.parse::<f32>()?;
+    let pitch = pitch.parse::<f32>()?;
+    let roll = roll.parse::<f32>()?;
+    let crease_angle = match crease_angle {
+        Some(v) => Some(v.parse::<f32>()?.to_radians()),
+        None => None,
+    };
+
+    let mut model = load_model(filename)?;
+    if !objects.is_empty() {
+        model.retain_sub_meshes(&objects)?;
+    }
+    model.remove_sub_meshes(&hidden_objects)?;
+    if let Some(crease_angle) = crease_angle {
+        model.compute_vertex_normals(crease_angle);
+    }
+    model.scale(scale.0, scale.1, scale.2);
+    model.rotate(yaw, pitch, roll);
+    model.translate(translate.0, translate.1, translate.2);
+
+    Ok(model)
+}
+
+/// Writes a depth buffer as a grayscale PFM image, whose rows run from the bottom up.
+fn write_depth_map(
+    filename: &str,
+    (width, height): (u16, u16),
+    depths: &[f32],
+) -> Result<(), RangleError> {
+    let mut writer = BufWriter::new(File::create(filename)?);
+
+    // A negative scale marks little-endian samples.
+    write!(writer, "Pf\n{} {}\n-1.0\n", width, height)?;
+    for row in depths.chunks(width as usize).rev() {
+        for depth in row {
+            writer.write_all(&depth.to_le_bytes())?;
+        }
+    }
+    writer.flush()?;
+
+    Ok(())
+}
+
+fn convert(matches: &ArgMatches) -> Result<(), RangleError> {
+    let output = matches.value_of("output").unwrap();
+    let bake = matches.is_present("bake");
+
+    let model = prepare_model(matches)?;
+
+    let extension = Path::new(output)
+        .extension()
+        .map(|v| v.to_string_lossy().to_lowercase());
+
+    match extension.as_deref() {
+        Some("obj") => model.write_obj(output, bake),
+        Some("stl") => model.write_stl(output, bake),
+        Some("ply") => model.write_ply(output, bake),
+        _ => {
+            eprintln!("Unknown output format: {}", output);
+            exit(2);
+        }
+    }
+}
+
 fn main() -> Result<(), RangleError> {
     let matches = App::new("J-Grangle")
+        .setting(AppSettings::SubcommandsNegateReqs)
+        .setting(AppSettings::ArgsNegateSubcommands)
+        .subcommand(
+            SubCommand::with_name("convert")
+                .about("Converts a model to another format")
+                .arg(
+                    Arg::with_name("filename")
+                        .index(1)
+                        .takes_value(true)
+                        .value_name("IN")
+                        .required(true)
+                        .help("Path to the model file (obj, stl or ply, chosen by extension)"),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .index(2)
+                        .takes_value(true)
+                        .value_name("OUT")
+                        .required(true)
+                        .help("Path to write the model to (obj, stl or ply, chosen by extension)"),
+                )
+                .arg(Arg::with_name("bake").long("bake").help(
+                    "Apply the model transform, including centering, to the written vertices",
+                ))
+                .args(&model_args()),
+        )
+        .args(&model_args())
         .arg(
             Arg::with_name("filename")
                 .index(1)
                 .takes_value(true)
                 .value_name("OBJ-FILE")
                 .required(true)
-                .help("Path to obj file"),
+                .help("Path to the model file (obj, stl or ply, chosen by extension)"),
         )
         .arg(
             Arg::with_name("width")
@@ -97,13 +317,20 @@ fn main() -> Result<(), RangleError> {
             Arg::with_name("shader")
                 .long("shader")
                 .takes_value(true)
-                .possible_values(&["normal", "solid"])
+                .possible_values(&[
+                    "normal",
+                    "solid",
+                    "color",
+                    "lambert",
+                    "gouraud",
+                    "phong",
+                    "blinn-phong",
+                ])
                 .default_value("normal")
                 .help("The pre-compiled set of shaders to use"),
         )
         .arg(
             Arg::with_name("shader_color")
-                .required_if("shader", "solid")
                 .short("c")
                 .long("color")
                 .takes_value(true)
@@ -111,69 +338,252 @@ fn main() -> Result<(), RangleError> {
                 .help("The color of the object"),
         )
         .arg(
-            Arg::with_name("scale")
-                .short("s")
-                .long("scale")
+            Arg::with_name("materials")
+                .long("materials")
+                .help("Color faces by the diffuse color of their mtl material"),
+        )
+        .arg(
+            Arg::with_name("light_dir")
+                .long("light-dir")
                 .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
                 .allow_hyphen_values(true)
-                .default_value("1 1 1")
-                .help("The x y z values to scale the model by"),
+                .value_name("x y z")
+                .help("Add a directional light shining in this direction (may be repeated)"),
         )
         .arg(
-            Arg::with_name("translate")
-                .short("t")
-                .long("translate")
+            Arg::with_name("light_pos")
+                .long("light-pos")
                 .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
                 .allow_hyphen_values(true)
-                .default_value("0 0 0")
-                .help("The xyz values to translate the model by"),
+                .value_name("x y z")
+                .help("Add a point light at this position (may be repeated)"),
         )
         .arg(
-            Arg::with_name("yaw")
-                .long("yaw")
+            Arg::with_name("light_color")
+                .long("light-color")
                 .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
                 .allow_hyphen_values(true)
-                .default_value("0")
-                .help("The value in radians to rotate the model around the y-axis"),
+                .value_name("r g b")
+                .help(
+                    "The color of each light, directional lights first, in the order given \
+                     [default: 1 1 1]",
+                ),
         )
         .arg(
-            Arg::with_name("pitch")
-                .long("pitch")
+            Arg::with_name("ambient")
+                .long("ambient")
                 .takes_value(true)
                 .allow_hyphen_values(true)
-                .default_value("0")
-                .help("The value in radians to rotate the model around the x-axis"),
+                .default_value("0.1 0.1 0.1")
+                .help("The ambient light color"),
+        )
+        .arg(
+            Arg::with_name("shininess")
+                .long("shininess")
+                .takes_value(true)
+                .default_value("32")
+                .help("The specular exponent of surfaces without a material"),
+        )
+        .arg(
+            Arg::with_name("quantize")
+                .long("quantize")
+                .takes_value(true)
+                .value_name("LEVELS")
+                .help("Round each color channel to this many levels, shrinking the jgraph output"),
         )
         .arg(
-            Arg::with_name("roll")
-                .long("roll")
+            Arg::with_name("vector")
+                .long("vector")
+                .help("Draw depth-sorted polygons, lines or points instead of pixels"),
+        )
+        .arg(
+            Arg::with_name("cull")
+                .long("cull")
+                .takes_value(true)
+                .possible_values(&["none", "back", "front"])
+                .default_value("none")
+                .help("Skip triangles facing away from (back) or towards (front) the camera"),
+        )
+        .arg(
+            Arg::with_name("front_face")
+                .long("front-face")
+                .takes_value(true)
+                .possible_values(&["ccw", "cw"])
+                .default_value("ccw")
+                .help("The winding order of front facing triangles"),
+        )
+        .arg(
+            Arg::with_name("threads")
+                .long("threads")
+                .takes_value(true)
+                .value_name("N")
+                .help("The number of threads to render with [default: one per CPU]"),
+        )
+        .arg(
+            Arg::with_name("aa")
+                .long("aa")
+                .takes_value(true)
+                .value_name("MODE")
+                .default_value("none")
+                .help(
+                    "Smooth edges by rendering N times larger (ssaa:N) or by testing N samples \
+                     per pixel (msaa:N, with N 2, 4 or 8)",
+                ),
+        )
+        .arg(
+            Arg::with_name("aa_filter")
+                .long("aa-filter")
+                .takes_value(true)
+                .possible_values(&["box", "lanczos"])
+                .default_value("box")
+                .help("The filter which shrinks supersampled images"),
+        )
+        .arg(
+            Arg::with_name("line_width")
+                .long("line-width")
+                .takes_value(true)
+                .default_value("1")
+                .help("The width of lines in pixels"),
+        )
+        .arg(
+            Arg::with_name("line_cap")
+                .long("line-cap")
+                .takes_value(true)
+                .possible_values(&["butt", "square", "round"])
+                .default_value("butt")
+                .help("How the ends of lines wider than a pixel are drawn"),
+        )
+        .arg(
+            Arg::with_name("smooth_lines")
+                .long("smooth-lines")
+                .help("Anti-alias lines by blending their edges into the image"),
+        )
+        .arg(
+            Arg::with_name("point_size")
+                .long("point-size")
+                .takes_value(true)
+                .default_value("1")
+                .help("The size of points in pixels"),
+        )
+        .arg(
+            Arg::with_name("point_shape")
+                .long("point-shape")
+                .takes_value(true)
+                .possible_values(&["square", "round"])
+                .default_value("square")
+                .help("The shape points are drawn as"),
+        )
+        .arg(
+            Arg::with_name("wireframe_color")
+                .long("wireframe-color")
                 .takes_value(true)
                 .allow_hyphen_values(true)
-                .default_value("0")
-                .help("The value in radians to rotate the model around the z-axis"),
+                .default_value("0 0 0")
+                .help("The color of the edges drawn over surfaces in solid-wireframe mode"),
+        )
+        .arg(
+            Arg::with_name("opacity")
+                .long("opacity")
+                .takes_value(true)
+                .default_value("1")
+                .help("The opacity of the object, except for faces whose material sets its own"),
+        )
+        .arg(
+            Arg::with_name("blend")
+                .long("blend")
+                .takes_value(true)
+                .possible_values(&["replace", "alpha", "additive", "multiply"])
+                .help(
+                    "How fragment colors are combined with the colors behind them [default: \
+                     alpha if --opacity or a material's d is below 1, otherwise replace]",
+                ),
+        )
+        .arg(
+            Arg::with_name("transparency")
+                .long("transparency")
+                .takes_value(true)
+                .possible_values(&["ordered", "sorted", "weighted"])
+                .default_value("ordered")
+                .help(
+                    "Blend translucent faces in drawing order, sorted back to front, or with \
+                     weighted blended order-independent transparency",
+                ),
+        )
+        .arg(
+            Arg::with_name("reversed_z")
+                .long("reversed-z")
+                .help("Store depth from 1 at the near plane to 0 at the far plane"),
+        )
+        .arg(
+            Arg::with_name("depth_map")
+                .long("depth-map")
+                .takes_value(true)
+                .value_name("FILE")
+                .conflicts_with("vector")
+                .help("Write the depth buffer to a PFM image"),
         )
         .arg(
             Arg::with_name("mode")
                 .short("m")
                 .long("mode")
                 .takes_value(true)
-                .possible_values(&["triangles", "lines", "points"])
+                .possible_values(&[
+                    "triangles",
+                    "lines",
+                    "points",
+                    "solid-wireframe",
+                    "hidden-line",
+                ])
                 .default_value("triangles")
                 .help("The display mode used to render the model"),
         )
         .get_matches();
 
-    let filename = matches.value_of("filename").unwrap();
+    if let Some(matches) = matches.subcommand_matches("convert") {
+        return convert(matches);
+    }
+
     let width = matches.value_of("width").unwrap();
     let height = matches.value_of("height").unwrap();
     let background_color = matches.value_of("background_color");
     let shader = matches.value_of("shader").unwrap();
     let color = matches.value_of("shader_color");
-    let scale = matches.value_of("scale");
-    let translate = matches.value_of("translate");
-    let yaw = matches.value_of("yaw").unwrap();
-    let pitch = matches.value_of("pitch").unwrap();
-    let roll = matches.value_of("roll").unwrap();
+    let use_materials = matches.is_present("materials");
+    let light_dirs: Vec<&str> = matches
+        .values_of("light_dir")
+        .map_or(vec![], |v| v.collect());
+    let light_positions: Vec<&str> = matches
+        .values_of("light_pos")
+        .map_or(vec![], |v| v.collect());
+    let light_colors: Vec<&str> = matches
+        .values_of("light_color")
+        .map_or(vec![], |v| v.collect());
+    let ambient = matches.value_of("ambient");
+    let shininess = matches.value_of("shininess").unwrap();
+    let quantize = matches.value_of("quantize");
+    let vector = matches.is_present("vector");
+    let cull = matches.value_of("cull").unwrap();
+    let front_face = matches.value_of("front_face").unwrap();
+    let threads = matches.value_of("threads");
+    let aa = matches.value_of("aa").unwrap();
+    let aa_filter = matches.value_of("aa_filter").unwrap();
+    let line_width = matches.value_of("line_width").unwrap();
+    let line_cap = matches.value_of("line_cap").unwrap();
+    let smooth_lines = matches.is_present("smooth_lines");
+    let point_size = matches.value_of("point_size").unwrap();
+    let point_shape = matches.value_of("point_shape").unwrap();
+    let wireframe_color = matches.value_of("wireframe_color");
+    let opacity = matches.value_of("opacity").unwrap();
+    let blend = matches.value_of("blend");
+    let transparency = matches.value_of("transparency").unwrap();
+    let reversed_z = matches.is_present("reversed_z");
+    let depth_map = matches.value_of("depth_map");
     let mode = matches.value_of("mode").unwrap();
 
     let width = width.parse::<u16>()?;
@@ -185,55 +595,240 @@ fn main() -> Result<(), RangleError> {
             exit(2);
         }
     };
-    let scale = match match_vec3(scale) {
-        Ok(v) => v,
-        Err(e) => {
-            eprintln!("{}", e);
-            exit(2);
-        }
+    let quantize = match quantize {
+        Some(v) => Some(v.parse::<u8>()?),
+        None => None,
     };
-    let translate = match match_vec3(translate) {
+    let cull = match cull {
+        "none" => CullMode::None,
+        "back" => CullMode::Back,
+        "front" => CullMode::Front,
+        _ => unreachable!(),
+    };
+    let front_face = match front_face {
+        "ccw" => FrontFace::CounterClockwise,
+        "cw" => FrontFace::Clockwise,
+        _ => unreachable!(),
+    };
+    let threads = match threads {
+        Some(v) => v.parse::<usize>()?,
+        None => thread::available_parallelism().map_or(1, |n| n.get()),
+    };
+    let aa_filter = match aa_filter {
+        "box" => ResolveFilter::Box,
+        "lanczos" => ResolveFilter::Lanczos,
+        _ => unreachable!(),
+    };
+    let aa = match match_anti_aliasing(aa, aa_filter) {
         Ok(v) => v,
         Err(e) => {
             eprintln!("{}", e);
             exit(2);
         }
     };
-    let yaw = yaw.parse::<f32>()?;
-    let pitch = pitch.parse::<f32>()?;
-    let roll = roll.parse::<f32>()?;
+    let line_width = line_width.parse::<f32>()?;
+    let line_cap = match line_cap {
+        "butt" => LineCap::Butt,
+        "square" => LineCap::Square,
+        "round" => LineCap::Round,
+        _ => unreachable!(),
+    };
+    let point_size = point_size.parse::<f32>()?;
+    let point_shape = match point_shape {
+        "square" => PointShape::Square,
+        "round" => PointShape::Round,
+        _ => unreachable!(),
+    };
     let mode = match mode {
         "triangles" => RangleMode::Triangles,
         "lines" => RangleMode::Lines,
         "points" => RangleMode::Points,
+        "solid-wireframe" => RangleMode::SolidWireframe,
+        "hidden-line" => RangleMode::HiddenLine,
+        _ => unreachable!(),
+    };
+    let opacity = opacity.parse::<f32>()?;
+    let blend = blend.map(|blend| match blend {
+        "replace" => BlendMode::Replace,
+        "alpha" => BlendMode::Alpha,
+        "additive" => BlendMode::Additive,
+        "multiply" => BlendMode::Multiply,
         _ => unreachable!(),
+    });
+    let transparency = match transparency {
+        "ordered" => Transparency::Ordered,
+        "sorted" => Transparency::Sorted,
+        "weighted" => Transparency::WeightedBlended,
+        _ => unreachable!(),
+    };
+    let wireframe_color = match match_vec3(wireframe_color) {
+        Ok(v) => Vec4::new(v.0, v.1, v.2, 1.0),
+        Err(e) => {
+            eprintln!("{}", e);
+            exit(2);
+        }
     };
 
     {
-        let display = JgraphDisplay::new(width, height, background_color)?;
+        let mut display = JgraphDisplay::new(width, height, background_color)?;
+        display.set_quantize_levels(quantize);
 
         let mut rangle = Rangle::new(Box::new(display))?;
         rangle.set_display_mode(mode);
+        if vector {
+            rangle.set_output(RangleOutput::Vector);
+        }
+        rangle.set_cull_mode(cull);
+        rangle.set_front_face(front_face);
+        rangle.set_thread_count(threads);
+        rangle.set_anti_aliasing(aa)?;
+        rangle.set_line_width(line_width);
+        rangle.set_line_cap(line_cap);
+        rangle.set_smooth_lines(smooth_lines);
+        rangle.set_point_size(point_size);
+        rangle.set_point_shape(point_shape);
+        rangle.set_wireframe_color(wireframe_color);
+        if reversed_z {
+            rangle.set_depth_state(DepthState::reversed());
+        }
+        rangle.set_transparency(transparency);
+
+        let model = prepare_model(&matches)?;
+
+        // Translucent objects are blended unless asked otherwise, so that they show through.
+        let translucent = opacity < 1.0
+            || use_materials
+                && model
+                    .get_materials()
+                    .iter()
+                    .any(|material| material.dissolve < 1.0);
+        rangle.set_blend_mode(blend.unwrap_or(if translucent {
+            BlendMode::Alpha
+        } else {
+            BlendMode::Replace
+        }));
 
-        let mut model = Model::from_file(filename)?;
         let shader = match shader {
-            "normal" => get_normal_shader(width, height, scale, (yaw, pitch, roll), translate, &mut model)?,
+            "normal" => get_normal_shader(width, height, opacity, use_materials, &model)?,
             "solid" => {
-                let color = match match_vec3(color) {
-                    Ok(v) => (v.0, v.1, v.2, 1.0),
+                // Faces without a material still need a color to fall back on.
+                let color = if use_materials && color.is_none() {
+                    (1.0, 1.0, 1.0, opacity)
+                } else {
+                    match match_vec3(color) {
+                        Ok(v) => (v.0, v.1, v.2, opacity),
+                        Err(e) => {
+                            eprintln!("{}", e);
+                            exit(2);
+                        }
+                    }
+                };
+                get_solid_shader(width, height, color, use_materials, &model)?
+            }
+            "lambert" | "gouraud" | "phong" | "blinn-phong" => {
+                let color = match match_vec3(color.or(Some("1 1 1"))) {
+                    Ok(v) => (v.0, v.1, v.2, opacity),
+                    Err(e) => {
+                        eprintln!("{}", e);
+                        exit(2);
+                    }
+                };
+                let ambient = match match_vec3(ambient) {
+                    Ok(v) => v.into(),
+                    Err(e) => {
+                        eprintln!("{}", e);
+                        exit(2);
+                    }
+                };
+
+                let mut lights = vec![];
+                for &direction in &light_dirs {
+                    match match_vec3(Some(direction)) {
+                        Ok(v) => lights.push(Light::Directional {
+                            direction: v.into(),
+                            color: Vec3::ONE,
+                        }),
+                        Err(e) => {
+                            eprintln!("{}", e);
+                            exit(2);
+                        }
+                    }
+                }
+                for &position in &light_positions {
+                    match match_vec3(Some(position)) {
+                        Ok(v) => lights.push(Light::Point {
+                            position: v.into(),
+                            color: Vec3::ONE,
+                        }),
+                        Err(e) => {
+                            eprintln!("{}", e);
+                            exit(2);
+                        }
+                    }
+                }
+                if lights.is_empty() {
+                    lights.push(Light::Directional {
+                        direction: Vec3::new(0.0, -1.0, -1.0),
+                        color: Vec3::ONE,
+                    });
+                }
+                if lights.len() > MAX_LIGHTS {
+                    eprintln!("At most {} lights are supported", MAX_LIGHTS);
+                    exit(2);
+                }
+
+                for (light, &light_color) in lights.iter_mut().zip(&light_colors) {
+                    let light_color = match match_vec3(Some(light_color)) {
+                        Ok(v) => v.into(),
+                        Err(e) => {
+                            eprintln!("{}", e);
+                            exit(2);
+                        }
+                    };
+
+                    match light {
+                        Light::Directional { color, .. } | Light::Point { color, .. } => {
+                            *color = light_color
+                        }
+                    }
+                }
+
+                let lighting = Lighting {
+                    model: match shader {
+                        "lambert" => LightingModel::Lambert,
+                        "gouraud" => LightingModel::Gouraud,
+                        "phong" => LightingModel::Phong,
+                        "blinn-phong" => LightingModel::BlinnPhong,
+                        _ => unreachable!(),
+                    },
+                    lights,
+                    ambient,
+                    shininess: shininess.parse::<f32>()?,
+                };
+
+                get_lit_shader(width, height, color, use_materials, &lighting, &model)?
+            }
+            "color" => {
+                // Models without vertex colors are drawn in a single color instead.
+                let color = match match_vec3(color.or(Some("1 1 1"))) {
+                    Ok(v) => (v.0, v.1, v.2, opacity),
                     Err(e) => {
                         eprintln!("{}", e);
                         exit(2);
                     }
                 };
-                get_solid_shader(width, height, color, scale, (yaw, pitch, roll), translate, &mut model)?
-            },
+                get_color_shader(width, height, color, &model)?
+            }
             _ => unreachable!(),
         };
 
-        let _ = rangle.add_model(model, shader.clone());
+        rangle.add_model(model, shader)?;
 
         rangle.render_scene()?;
+
+        if let Some(filename) = depth_map {
+            write_depth_map(filename, rangle.get_size(), rangle.get_depth_buffer())?;
+        }
     }
 
     Ok(())